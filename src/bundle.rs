@@ -0,0 +1,561 @@
+//! Bundles are `.tar.gz` exports of a fetched resource, for moving it to a machine
+//! that can't fetch it itself.
+//!
+//! A bundle holds the resource's whole tree, `.git` and core dependencies included, under
+//! a directory named after the resource, followed by a `BUNDLE` manifest. The manifest is
+//! plain text, one `key value` pair per line:
+//!
+//! ```text
+//! better-install bundle 1
+//! resource core
+//! name SCAII
+//! commit 0123abcd...
+//! source https://github.com/SCAII/SCAII
+//! sha1 <hex digest> SCAII/README.md
+//! ```
+//!
+//! with a `sha1` line for every file. It comes last so a bundle can be written in one pass,
+//! which is why `unpack` extracts next to the target and only moves the tree into place
+//! once everything has checked out.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use error;
+use fs2;
+use get::GetReport;
+use util;
+
+/// The file name of the manifest inside a bundle.
+pub const MANIFEST: &str = "BUNDLE";
+/// The first line of every manifest, the number is bumped whenever the format changes.
+pub const FORMAT_HEADER: &str = "better-install bundle";
+pub const FORMAT_VERSION: u32 = 1;
+/// The manifest is read into memory, and even a huge tree's is far smaller than this.
+const MAX_MANIFEST_SIZE: u64 = 64 << 20;
+
+/// What a bundle's manifest says it holds.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Manifest {
+    pub resource: String,
+    pub name: String,
+    pub commit: Option<String>,
+    pub source: String,
+    /// The SHA-1 of each file, by its path in the archive
+    pub files: Vec<(String, String)>,
+}
+
+impl Manifest {
+    fn parse(manifest: &str) -> error::Result<Self> {
+        let mut lines = manifest.lines();
+
+        let version = match lines.next() {
+            Some(header) if header.starts_with(FORMAT_HEADER) => &header[FORMAT_HEADER.len()..],
+            _ => bail!("The {} manifest doesn't start with '{}'", MANIFEST, FORMAT_HEADER),
+        };
+        ensure!(
+            version.trim() == FORMAT_VERSION.to_string(),
+            "The bundle is in format {}, but only format {} is supported (Hint: unpack it with \
+             the version of better-install that made it)",
+            version.trim(),
+            FORMAT_VERSION
+        );
+
+        let mut parsed = Manifest::default();
+        for line in lines {
+            let mut parts = line.splitn(2, ' ');
+            let (key, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+            match key {
+                "resource" => parsed.resource = value.to_string(),
+                "name" => parsed.name = value.to_string(),
+                "commit" if value != "-" => parsed.commit = Some(value.to_string()),
+                "commit" => {}
+                "source" => parsed.source = value.to_string(),
+                "sha1" => {
+                    let mut parts = value.splitn(2, ' ');
+                    match (parts.next(), parts.next()) {
+                        (Some(sha1), Some(path)) => {
+                            parsed.files.push((path.to_string(), sha1.to_string()))
+                        }
+                        _ => bail!("Malformed {} line '{}'", MANIFEST, line),
+                    }
+                }
+                _ => bail!("Unknown {} line '{}'", MANIFEST, line),
+            }
+        }
+
+        ensure!(
+            !parsed.name.is_empty() && !parsed.resource.is_empty(),
+            "The {} manifest doesn't name the resource it holds",
+            MANIFEST
+        );
+
+        Ok(parsed)
+    }
+}
+
+/// Packages the resource `report` describes, fetched from `source`, into the bundle `file`.
+/// A partly written bundle is removed if anything goes wrong.
+pub fn create(report: &GetReport, source: &str, file: &Path) -> error::Result<()> {
+    use error::ResultExt;
+
+    let result = write_bundle(report, source, file);
+    if result.is_err() {
+        let _ = fs::remove_file(file);
+    }
+
+    result.chain_err(|| format!("Could not bundle '{}' into '{}'", report.path.display(), file.display()))
+}
+
+fn write_bundle(report: &GetReport, source: &str, file: &Path) -> error::Result<()> {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use util::sha1::{sha1, to_hex};
+    use util::tar::TarWriter;
+
+    let name = match report.path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => bail!("'{}' has no name to bundle it under", report.path.display()),
+    };
+
+    let mut manifest = format!(
+        "{} {}\nresource {}\nname {}\ncommit {}\nsource {}\n",
+        FORMAT_HEADER,
+        FORMAT_VERSION,
+        report.resource,
+        name,
+        report.commit.as_ref().map_or("-", |commit| &**commit),
+        source
+    );
+
+    let out = GzEncoder::new(fs::File::create(file)?, Compression::default());
+    let mut tar = TarWriter::new(out);
+    tar.append_dir(&name, 0o755)?;
+
+    for entry in walk(&report.path)? {
+        let relative = entry.strip_prefix(&report.path)?;
+        let archived = format!("{}/{}", name, archive_path(relative)?);
+        let metadata = fs::symlink_metadata(&entry)?;
+
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&entry)?;
+            tar.append_symlink(&archived, &archive_path(&target)?)?;
+        } else if metadata.is_dir() {
+            tar.append_dir(&archived, mode(&metadata, 0o755))?;
+        } else {
+            let contents = fs::read(&entry)?;
+            manifest.push_str(&format!("sha1 {} {}\n", to_hex(&sha1(&contents)), archived));
+            tar.append_file(&archived, mode(&metadata, 0o644), &contents)?;
+        }
+    }
+
+    tar.append_file(MANIFEST, 0o644, manifest.as_bytes())?;
+    tar.finish()?.finish()?;
+
+    Ok(())
+}
+
+/// Extracts the bundle `file` to `target`, or to `~/.scaii/git/<NAME>` if it's `None`,
+/// after checking every file against the manifest. `resource` is what the bundle must hold,
+/// if given. An existing target is only replaced if `force` is set, and it's at least
+/// `depth_limit` levels below the SCAII home if it's under it at all.
+pub fn unpack(
+    file: &Path,
+    target: Option<&Path>,
+    resource: Option<&str>,
+    force: bool,
+    depth_limit: usize,
+    scaii_dir: &Path,
+) -> error::Result<(Manifest, PathBuf)> {
+    use flate2::read::GzDecoder;
+    use util::tar::{EntryKind, TarReader};
+    use error::{ErrorKind, ResultExt};
+
+    let input = fs::File::open(file)
+        .chain_err(|| ErrorKind::CannotReadError(format!("{}", file.display())))?;
+    let mut tar = TarReader::new(GzDecoder::new(input));
+
+    // Bundles start with the directory everything else is in
+    let name = match tar.next_entry()? {
+        Some(ref entry) if entry.kind == EntryKind::Dir => entry.path.trim_end_matches('/').to_string(),
+        _ => bail!("'{}' is not a bundle made by `get --bundle`", file.display()),
+    };
+    ensure!(
+        !name.is_empty() && !name.contains('/') && name != "..",
+        "'{}' is not a bundle made by `get --bundle`",
+        file.display()
+    );
+
+    let target = match target {
+        Some(target) => target.to_path_buf(),
+        None => scaii_dir.join("git").join(&name),
+    };
+    ensure!(
+        force || !target.exists(),
+        "'{}' already exists (Hint: pass -f to replace it with the bundle)",
+        target.display()
+    );
+
+    // Next to the target, so moving it into place can be a rename
+    let staging = target.with_file_name(format!(".{}.unbundling", name));
+    if staging.exists() {
+        fs2::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)
+        .chain_err(|| ErrorKind::CannotCreateError(format!("{}", staging.display())))?;
+
+    let result = extract(&mut tar, &name, &staging).and_then(|manifest| {
+        check(&manifest, &name, resource, &staging)?;
+        Ok(manifest)
+    });
+    let manifest = match result {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            let _ = fs2::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+
+    if target.exists() {
+        util::check_removable(&target, scaii_dir, depth_limit)?;
+        fs2::remove_dir_all(&target)
+            .chain_err(|| ErrorKind::CannotCleanError(format!("{}", target.display())))?;
+    }
+    util::move_path(&staging, &target)?;
+
+    Ok((manifest, target))
+}
+
+/// Writes every entry under `name/` into `staging`, returning the manifest with its
+/// `files` replaced by the checksums of what was actually extracted.
+fn extract<R: ::std::io::Read>(
+    tar: &mut ::util::tar::TarReader<R>,
+    name: &str,
+    staging: &Path,
+) -> error::Result<Manifest> {
+    use std::io::Read;
+    use util::sha1::{sha1_reader, to_hex};
+    use util::tar::{EntryKind, Extractor};
+
+    let (mut manifest, mut extracted) = (None, Vec::new());
+    let prefix = format!("{}/", name);
+    let mut extractor = Extractor::new(staging, None);
+
+    while let Some(entry) = tar.next_entry()? {
+        if entry.path == MANIFEST {
+            ensure!(entry.size <= MAX_MANIFEST_SIZE, "The bundle's {} is too large", MANIFEST);
+            let mut contents = Vec::new();
+            tar.read_to_end(&mut contents)?;
+            manifest = Some(Manifest::parse(&String::from_utf8_lossy(&contents))?);
+            continue;
+        }
+
+        let relative = Path::new(if entry.path.starts_with(&prefix) {
+            &entry.path[prefix.len()..]
+        } else {
+            ""
+        });
+        extractor.extract(&entry, tar, relative, &format!("'{}'", name))?;
+        // What's on disk is checked, so files never have to be held in memory
+        if entry.kind == EntryKind::File {
+            let written = fs::File::open(staging.join(relative))?;
+            extracted.push((entry.path.clone(), to_hex(&sha1_reader(written)?)));
+        }
+    }
+
+    match manifest {
+        Some(mut manifest) => {
+            manifest.files.sort();
+            extracted.sort();
+            ensure!(
+                manifest.files == extracted,
+                "The bundle's contents don't match its {} manifest{}",
+                MANIFEST,
+                first_difference(&manifest.files, &extracted)
+            );
+            Ok(manifest)
+        }
+        None => bail!("The bundle has no {} manifest", MANIFEST),
+    }
+}
+
+/// Describes the first file the manifest and the extracted files disagree on.
+fn first_difference(expected: &[(String, String)], extracted: &[(String, String)]) -> String {
+    for (path, sha1) in expected {
+        match extracted.iter().find(|file| &file.0 == path) {
+            None => return format!(", '{}' is missing", path),
+            Some(file) if &file.1 != sha1 => return format!(", '{}' is damaged", path),
+            _ => {}
+        }
+    }
+
+    match extracted.iter().find(|file| !expected.iter().any(|listed| listed.0 == file.0)) {
+        Some(file) => format!(", '{}' isn't listed", file.0),
+        None => String::new(),
+    }
+}
+
+/// Checks the manifest against what the caller asked for and what was extracted.
+fn check(
+    manifest: &Manifest,
+    name: &str,
+    resource: Option<&str>,
+    staging: &Path,
+) -> error::Result<()> {
+    use verify::head_commit;
+
+    ensure!(
+        manifest.name == name,
+        "The bundle's manifest names '{}', but it holds '{}'",
+        manifest.name,
+        name
+    );
+    if let Some(resource) = resource {
+        ensure!(
+            manifest.resource == resource,
+            "The bundle holds the {} '{}', not a {}",
+            manifest.resource,
+            name,
+            resource
+        );
+    }
+
+    if let Some(ref commit) = manifest.commit {
+        let head = head_commit(staging);
+        ensure!(
+            head.as_ref() == Some(commit),
+            "The bundle should be at commit {}, but its checkout is at {}",
+            commit,
+            head.as_ref().map_or("no commit", |head| &**head)
+        );
+    }
+
+    Ok(())
+}
+
+/// Everything under `dir`, parents before their contents and otherwise sorted, so
+/// bundles of the same tree come out the same. Symlinks aren't followed.
+fn walk(dir: &Path) -> error::Result<Vec<PathBuf>> {
+    let mut children = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    children.sort();
+
+    let mut entries = Vec::new();
+    for child in children {
+        let is_dir = fs::symlink_metadata(&child)?.is_dir();
+        entries.push(child.clone());
+        if is_dir {
+            entries.extend(walk(&child)?);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// `path` with `/` separators, as tar wants.
+fn archive_path(path: &Path) -> error::Result<String> {
+    let parts = path.components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>();
+
+    match parts {
+        Some(parts) => Ok(parts.join("/")),
+        None => bail!("'{}' is not valid UTF-8, so can't be bundled", path.display()),
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata, _default: u32) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata, default: u32) -> u32 {
+    default
+}
+
+#[cfg(test)]
+mod test {
+    use super::{create, unpack, Manifest};
+    use get::GetReport;
+    use util::fixture::temp_dir;
+    use std::fs;
+    use fs2;
+
+    #[test]
+    fn bundle_tree_and_manifest() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let dir = temp_dir("bundle-create");
+        let core = dir.join("SCAII");
+        fs::create_dir_all(core.join("viz/js")).unwrap();
+        fs::write(core.join("README.md"), b"readme").unwrap();
+        fs::write(core.join("viz/js/app.js"), b"app").unwrap();
+
+        let report = GetReport {
+            resource: "core",
+            path: core.clone(),
+            commit: Some("abc123".to_string()),
+            ..GetReport::default()
+        };
+        let file = dir.join("core.tar.gz");
+        create(&report, "https://github.com/SCAII/SCAII", &file).unwrap();
+
+        let mut tar = Vec::new();
+        GzDecoder::new(fs::File::open(&file).unwrap()).read_to_end(&mut tar).unwrap();
+        let text = String::from_utf8_lossy(&tar);
+
+        let order: Vec<_> = ["SCAII/README.md", "SCAII/viz/", "SCAII/viz/js/app.js", "BUNDLE"]
+            .iter()
+            .map(|name| text.find(name).unwrap())
+            .collect();
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert!(text.contains(
+            "better-install bundle 1\nresource core\nname SCAII\ncommit abc123\n\
+             source https://github.com/SCAII/SCAII\n\
+             sha1 f78a71af8bbf8cc2f6f313549d4da14bd3771359 SCAII/README.md\n\
+             sha1 7d1043473d55bfa90e8530d35801d4e381bc69f0 SCAII/viz/js/app.js\n"
+        ));
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_bundle_is_removed() {
+        let dir = temp_dir("bundle-missing");
+        let report = GetReport {
+            resource: "rts",
+            path: dir.join("Sky-RTS"),
+            ..GetReport::default()
+        };
+        let file = dir.join("rts.tar.gz");
+
+        assert!(create(&report, "https://github.com/SCAII/Sky-RTS", &file).is_err());
+        assert!(!file.exists());
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_versions() {
+        let manifest = "better-install bundle 1\nresource rts\nname Sky-RTS\ncommit -\n\
+                        source https://github.com/SCAII/Sky-RTS\nsha1 00ff Sky-RTS/a b.txt\n";
+        assert_eq!(
+            Manifest::parse(manifest).unwrap(),
+            Manifest {
+                resource: "rts".to_string(),
+                name: "Sky-RTS".to_string(),
+                commit: None,
+                source: "https://github.com/SCAII/Sky-RTS".to_string(),
+                files: vec![("Sky-RTS/a b.txt".to_string(), "00ff".to_string())],
+            }
+        );
+
+        let future = Manifest::parse("better-install bundle 2\nresource rts\nname Sky-RTS\n");
+        assert!(future.unwrap_err().to_string().contains("format 2"));
+        assert!(Manifest::parse("something else 1\n").is_err());
+        assert!(Manifest::parse("better-install bundle 1\nresource rts\n").is_err());
+        assert!(Manifest::parse("better-install bundle 1\nflavor vanilla\n").is_err());
+    }
+
+    #[test]
+    fn bundle_round_trip() {
+        let dir = temp_dir("bundle-round-trip");
+        let rts = dir.join("built").join("Sky-RTS");
+        fs::create_dir_all(rts.join("src")).unwrap();
+        fs::write(rts.join("src/main.rs"), b"fn main() {}").unwrap();
+
+        let report = GetReport {
+            resource: "rts",
+            path: rts.clone(),
+            ..GetReport::default()
+        };
+        let file = dir.join("rts.tar.gz");
+        create(&report, "https://github.com/SCAII/Sky-RTS", &file).unwrap();
+
+        let (manifest, target) = unpack(&file, None, Some("rts"), false, 2, &dir).unwrap();
+        assert_eq!(manifest.source, "https://github.com/SCAII/Sky-RTS");
+        assert_eq!(target, dir.join("git").join("Sky-RTS"));
+        assert_eq!(fs::read(target.join("src/main.rs")).unwrap(), b"fn main() {}");
+
+        // Not over an existing copy unless forced, nor as the wrong resource
+        assert!(unpack(&file, None, Some("rts"), false, 2, &dir).is_err());
+        assert!(unpack(&file, None, Some("core"), true, 2, &dir).is_err());
+        assert!(target.join("src/main.rs").exists());
+
+        fs::write(target.join("src/main.rs"), b"changed").unwrap();
+        unpack(&file, None, None, true, 2, &dir).unwrap();
+        assert_eq!(fs::read(target.join("src/main.rs")).unwrap(), b"fn main() {}");
+        assert!(!dir.join("git").join(".Sky-RTS.unbundling").exists());
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_bundle_is_rejected() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use util::tar::TarWriter;
+
+        let dir = temp_dir("bundle-damaged");
+        let file = dir.join("rts.tar.gz");
+
+        let out = GzEncoder::new(fs::File::create(&file).unwrap(), Compression::default());
+        let mut tar = TarWriter::new(out);
+        tar.append_dir("Sky-RTS", 0o755).unwrap();
+        tar.append_file("Sky-RTS/a.txt", 0o644, b"tampered").unwrap();
+        let manifest = "better-install bundle 1\nresource rts\nname Sky-RTS\ncommit -\nsource x\n\
+                        sha1 7d1043473d55bfa90e8530d35801d4e381bc69f0 Sky-RTS/a.txt\n";
+        tar.append_file("BUNDLE", 0o644, manifest.as_bytes()).unwrap();
+        tar.finish().unwrap().finish().unwrap();
+
+        let err = unpack(&file, None, None, false, 2, &dir).unwrap_err();
+        assert!(err.to_string().contains("'Sky-RTS/a.txt' is damaged"), "{}", err);
+        assert!(!dir.join("git").join("Sky-RTS").exists());
+        assert!(!dir.join("git").join(".Sky-RTS.unbundling").exists());
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_symlink_escapes() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use util::tar::TarWriter;
+
+        let dir = temp_dir("bundle-escapes");
+        let file = dir.join("rts.tar.gz");
+        let unpack_with_link = |target: &str| {
+            let out = GzEncoder::new(fs::File::create(&file).unwrap(), Compression::default());
+            let mut tar = TarWriter::new(out);
+            tar.append_dir("Sky-RTS", 0o755).unwrap();
+            tar.append_symlink("Sky-RTS/x", target).unwrap();
+            tar.append_file("Sky-RTS/x/passwd", 0o644, b"owned").unwrap();
+            let manifest = "better-install bundle 1\nresource rts\nname Sky-RTS\ncommit -\n\
+                            source x\n";
+            tar.append_file("BUNDLE", 0o644, manifest.as_bytes()).unwrap();
+            tar.finish().unwrap().finish().unwrap();
+
+            unpack(&file, None, None, false, 2, &dir).unwrap_err()
+        };
+
+        // The staging directory is `git/.Sky-RTS.unbundling`, so this is the SCAII home
+        let err = unpack_with_link("../..");
+        assert!(err.to_string().contains("outside of 'Sky-RTS'"), "{}", err);
+        assert!(!dir.join("passwd").exists());
+
+        let err = unpack_with_link(dir.to_str().unwrap());
+        assert!(err.to_string().contains("absolute path"), "{}", err);
+        assert!(!dir.join("passwd").exists());
+        assert!(!dir.join("git").join(".Sky-RTS.unbundling").exists());
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+}
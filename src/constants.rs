@@ -9,7 +9,76 @@ pub const DEFAULT_BRANCH: &'static str = "master";
 pub const CLOSURE_LIB_URL: &'static str =
     "https://github.com/google/closure-library/archive/v20171112.zip";
 pub const CLOSURE_LIB_BYTES: usize = 7_032_575;
+pub const CLOSURE_LIB_DIR: &'static str = "closure_library";
 
 pub const PROTOBUF_JS_URL: &'static str =
     "https://github.com/google/protobuf/releases/download/v3.5.1/protobuf-js-3.5.1.zip";
 pub const PROTOBUF_JS_BYTES: usize = 5_538_299;
+pub const PROTOBUF_JS_DIR: &'static str = "protobuf_js";
+
+/// Where the core's visualization is, which its dependencies are extracted into, relative
+/// to the checkout unless `--dep-extract-dir` says otherwise
+pub const DEP_EXTRACT_DIR: &'static str = "viz/js";
+
+/// How many commits back from the tip `--find-commit` searches for a matching message
+pub const FIND_COMMIT_LIMIT: usize = 10_000;
+
+/// Where `--keep-archive` saves the downloaded archives, relative to the visualization
+pub const KEPT_ARCHIVES_DIR: &'static str = ".downloads";
+
+/// A dependency `get core` downloads into the core's visualization.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct CoreDependency {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub url: &'static str,
+    /// Where it's extracted to, relative to the visualization
+    pub dir: &'static str,
+}
+
+pub const CORE_DEPENDENCIES: [CoreDependency; 2] = [
+    CoreDependency {
+        name: "Google Closure Library",
+        version: "v20171112",
+        url: CLOSURE_LIB_URL,
+        dir: CLOSURE_LIB_DIR,
+    },
+    CoreDependency {
+        name: "protobuf_js",
+        version: "3.5.1",
+        url: PROTOBUF_JS_URL,
+        dir: PROTOBUF_JS_DIR,
+    },
+];
+
+pub const DEFAULT_CONNECT_RETRIES: u32 = 5;
+pub const CONNECT_RETRY_DELAY_MS: u64 = 250;
+
+pub const DEFAULT_TRANSFER_RETRIES: u32 = 2;
+pub const TRANSFER_RETRY_DELAY_MS: u64 = 2_000;
+
+/// HTTP statuses downloads are retried on unless `--retry-on-http` says otherwise
+pub const DEFAULT_RETRY_ON_HTTP: &str = "5xx,429";
+/// The longest a server's `Retry-After` is waited out, longer asks fail the download
+pub const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+/// A download slower than this many bytes a second for `LOW_SPEED_TIME_SECS` is stalled
+pub const LOW_SPEED_LIMIT_BYTES: u32 = 1_024;
+pub const LOW_SPEED_TIME_SECS: u64 = 60;
+
+/// How many levels below the SCAII home a directory has to be before `get` or `install`
+/// will delete it to replace it, as `git/<NAME>` is
+pub const DEFAULT_DEPTH_LIMIT: usize = 2;
+
+/// How many of the commits a `get --merge` brought in `--show-changes` prints by default
+pub const DEFAULT_CHANGES_LIMIT: usize = 20;
+
+/// Archives declaring more entries than this are refused unless `--max-archive-entries`
+/// says otherwise
+pub const DEFAULT_MAX_ARCHIVE_ENTRIES: usize = 100_000;
+
+/// Where `install` puts the binaries it built, relative to the SCAII home
+pub const BIN_DIR: &str = "bin";
+/// Where `install` records what it put there, one file per resource, relative to the
+/// SCAII home
+pub const INSTALLED_DIR: &str = "installed";
@@ -5,6 +5,7 @@ error_chain! {
         Curl(::curl::Error);
         Zip(::zip::result::ZipError);
         StripPrefix(::std::path::StripPrefixError);
+        Pattern(::glob::PatternError);
     Git(::git2::Error) #[cfg(unix)];
     WalkDir(::walkdir::Error) #[cfg(windows)];
     }
@@ -20,11 +21,150 @@ error_chain! {
             display("cannot create target path: '{}'", path)
         }
 
+        CannotReadError(path: String) {
+            description("cannot read file")
+            display("cannot read file: '{}'", path)
+        }
+
         GetFailure {
             description("could not execute get subcommand")
             display("could not execute get subcommand")
         }
+
+        UnzipFailure {
+            description("could not execute unzip subcommand")
+            display("could not execute unzip subcommand")
+        }
+
+        InstallFailure {
+            description("could not execute install subcommand")
+            display("could not execute install subcommand")
+        }
+
+        UninstallFailure {
+            description("could not execute uninstall subcommand")
+            display("could not execute uninstall subcommand")
+        }
+
+        Cancelled {
+            description("operation was cancelled")
+            display("operation was cancelled")
+        }
+
+        DownloadStalled(url: String) {
+            description("download stalled")
+            display("download stalled, too little data arrived for too long: '{}'", url)
+        }
+
+        MultiError(failures: Vec<String>) {
+            description("some entries could not be extracted")
+            display("{} entries could not be extracted:\n  {}", failures.len(), failures.join("\n  "))
+        }
+
+        SignatureVerificationFailed(object: String, reason: String) {
+            description("signature could not be verified")
+            display("the signature of {} could not be verified: {}", object, reason)
+        }
+
+        BuildFailed(command: String, status: String) {
+            description("build command failed")
+            display("build command `{}` failed with {}", command, status)
+        }
+        HttpStatus(url: String, status: u32) {
+            description("download answered with an HTTP error")
+            display("'{}' answered with HTTP {}", url, status)
+        }
+        InvalidUrl(url: String) {
+            description("not a URL or local path to fetch from")
+            display("not a URL or local path to fetch from: '{}'", url)
+        }
+        InvalidRef(name: String) {
+            description("not a branch, tag or commit name")
+            display("not a branch, tag or commit name: '{}'", name)
+        }
+        TooManyEntries(entries: usize, max: usize) {
+            description("archive has too many entries")
+            display("archive has {} entries, more than the maximum of {} (Hint: raise it with \
+                     `--max-archive-entries` if the archive is trusted)", entries, max)
+        }
+        SplitArchiveUnsupported {
+            description("archive is one volume of a split zip")
+            display("the archive is one volume of a split zip (Hint: join the volumes with \
+                     `zip -s 0 <ARCHIVE>.zip --out joined.zip`, or keep them together and \
+                     extract the `.zip` with `unzip`)")
+        }
+        NotInOneDirectory(parent: String, outside: Vec<String>) {
+            description("archive is not all in one directory")
+            display("the archive is not all in '{}', so its contents can't be extracted on \
+                     their own; outside of it: {}", parent, outside.join(", "))
+        }
+        CaseCollision(a: String, b: String) {
+            description("archive entries differ only in case")
+            display("'{}' and '{}' differ only in case, so one would overwrite the other on \
+                     this case-insensitive filesystem", a, b)
+        }
+        NoMatchingEntries(pattern: String) {
+            description("no archive entries matched the filter")
+            display("no archive entries matched the filter: '{}'", pattern)
+        }
     }
 }
 
 pub const CLEAN_EXIT: i32 = 0;
+
+// Exit statuses of `verify`, one per kind of problem
+pub const MISSING_CHECKOUT_EXIT: i32 = 2;
+pub const WRONG_COMMIT_EXIT: i32 = 3;
+pub const MISSING_DEPS_EXIT: i32 = 4;
+
+/// Renders every link of an error chain, outermost first, as a JSON object
+/// of the form `{"chain":["...","..."]}`.
+pub fn chain_to_json(err: &Error) -> String {
+    let links: Vec<String> = err.iter().map(|link| json_string(&link.to_string())).collect();
+
+    format!("{{\"chain\":[{}]}}", links.join(","))
+}
+
+/// Quotes and escapes `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_chain() {
+        let err: Error = Error::from(ErrorKind::CannotReadError("C:\\a \"b\"".to_string()))
+            .chain_err(|| ErrorKind::UnzipFailure);
+
+        assert_eq!(
+            chain_to_json(&err),
+            "{\"chain\":[\"could not execute unzip subcommand\",\
+             \"cannot read file: 'C:\\\\a \\\"b\\\"'\"]}"
+        );
+    }
+
+    #[test]
+    fn json_control_chars() {
+        assert_eq!(json_string("a\nb\u{1}"), "\"a\\nb\\u0001\"");
+    }
+}
@@ -1,9 +1,13 @@
 use clap::ArgMatches;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 
 use error;
 
-use util::{CdManager, NameOrPath};
+use util::{self, BufferPool, CdManager, CurlOptions, Download, NameOrPath, OutputLayout,
+           RetryPolicy, RetryStatuses, UnzipOptions};
+use util::regex::Regex;
+use util::ssh::HostKeyChecking;
 use constants::*;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -12,7 +16,224 @@ pub struct Get<'a> {
     branch: &'a str,
     path: PathBuf,
     force: bool,
+    merge: bool,
+    /// Leave an existing, complete checkout alone instead of erroring on it
+    only_if_missing: bool,
+    /// List up to this many of the commits a merge brought in
+    changes_limit: Option<usize>,
+    allow_dirty: bool,
+    force_deps: bool,
+    /// Leave dependency directories that aren't empty as they are instead of fetching them
+    skip_existing_deps: bool,
+    /// Fetch the core dependencies at the same time rather than one after the other
+    concurrent_deps: bool,
+    /// Print where dependency downloads were redirected to
+    verbose: bool,
+    /// The mode of the directories this creates, on Unix
+    dir_mode: Option<u32>,
+    /// Only look up the commit the branch is at, without cloning
+    resolve_only: bool,
+    /// Download a GitHub tarball of the branch instead of cloning, if the URL is on GitHub
+    tarball: bool,
+    /// Save the downloaded dependency archives instead of discarding them
+    keep_archives: bool,
+    /// Run `git gc` on the checkout once it's fetched
+    pack_objects: bool,
+    /// Check the GPG signature of what's checked out, against this keyring if there is one
+    verify_signature: Option<Option<&'a Path>>,
+    /// Replaces the origin of the built-in dependency URLs
+    dep_mirror: Option<&'a str>,
+    /// Where the core dependencies are extracted, relative to the checkout
+    dep_dir: PathBuf,
+    /// A local bare mirror to clone from instead of `url`
+    mirror: Option<PathBuf>,
     is_core: bool,
+    /// `core`, `rts` or `backend`
+    resource: &'static str,
+    scaii_dir: PathBuf,
+    /// How far below `scaii_dir` a directory must be for `force` to delete it
+    depth_limit: usize,
+    curl_options: CurlOptions,
+    clone_options: CloneOptions<'a>,
+}
+
+/// The outcome of a successful `Get::get`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct GetReport {
+    /// `core`, `rts` or `backend`
+    pub resource: &'static str,
+    /// Where the resource was cloned to
+    pub path: PathBuf,
+    /// The commit checked out, if it could be determined
+    pub commit: Option<String>,
+    /// Bytes downloaded for dependencies, not counting the clone itself
+    pub downloaded_bytes: u64,
+    /// The directory of each dependency downloaded and the URL it was served from,
+    /// after redirects
+    pub dependency_urls: Vec<(&'static str, String)>,
+    /// Non-fatal problems encountered along the way
+    pub warnings: Vec<String>,
+    /// Nothing was fetched, since `--only-if-missing` found the resource already there
+    pub skipped: bool,
+    /// The resource is an unpacked GitHub tarball, with no git history
+    pub tarball: bool,
+    /// What was checked out before a merge, if anything was
+    pub previous_commit: Option<String>,
+    /// The short hash and summary of the commits a merge brought in, newest first, with
+    /// `--show-changes`. There may be more than this, see `changes_count`
+    pub changes: Vec<String>,
+    /// How many commits the merge brought in, including any left out of `changes`
+    pub changes_count: usize,
+}
+
+impl GetReport {
+    /// The report as lines a POSIX shell can `eval`, such as
+    /// `SCAII_CORE_PATH=/home/me/.scaii/git/SCAII` and `SCAII_CORE_COMMIT=<hash>`.
+    /// The commit is left out if it isn't known, and a tarball adds `SCAII_CORE_TARBALL=1`.
+    /// Each downloaded dependency adds a line like `SCAII_CORE_PROTOBUF_JS_URL=<url>`.
+    pub fn to_env(&self) -> String {
+        let prefix = format!("SCAII_{}", self.resource.to_uppercase());
+
+        let mut env = format!(
+            "{}_PATH={}\n",
+            prefix,
+            shell_quote(&self.path.to_string_lossy())
+        );
+        if let Some(ref commit) = self.commit {
+            env.push_str(&format!("{}_COMMIT={}\n", prefix, shell_quote(commit)));
+        }
+        if self.tarball {
+            env.push_str(&format!("{}_TARBALL=1\n", prefix));
+        }
+        for &(dir, ref url) in &self.dependency_urls {
+            env.push_str(&format!(
+                "{}_{}_URL={}\n",
+                prefix,
+                dir.to_uppercase(),
+                shell_quote(url)
+            ));
+        }
+
+        env
+    }
+
+    /// The report as a single JSON object with `"status":"ok"`, for `--report-file`.
+    /// `elapsed` is how long the get took.
+    pub fn to_json(&self, elapsed: ::std::time::Duration) -> String {
+        use error::json_string;
+
+        let optional = |value: &Option<String>| match *value {
+            Some(ref value) => json_string(value),
+            None => "null".to_string(),
+        };
+        let list = |values: &[String]| {
+            let values: Vec<_> = values.iter().map(|value| json_string(value)).collect();
+            format!("[{}]", values.join(","))
+        };
+        let dependencies: Vec<_> = self.dependency_urls
+            .iter()
+            .map(|&(dir, ref url)| {
+                format!("{{\"dir\":{},\"url\":{}}}", json_string(dir), json_string(url))
+            })
+            .collect();
+
+        format!(
+            "{{\"status\":\"ok\",\"resource\":{},\"path\":{},\"commit\":{},\
+             \"previous_commit\":{},\"skipped\":{},\"tarball\":{},\"downloaded_bytes\":{},\
+             \"dependencies\":[{}],\"changes\":{},\"changes_count\":{},\"warnings\":{},\
+             \"elapsed_ms\":{}}}",
+            json_string(self.resource),
+            json_string(&self.path.to_string_lossy()),
+            optional(&self.commit),
+            optional(&self.previous_commit),
+            self.skipped,
+            self.tarball,
+            self.downloaded_bytes,
+            dependencies.join(","),
+            list(&self.changes),
+            self.changes_count,
+            list(&self.warnings),
+            elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+        )
+    }
+}
+
+/// Single quotes `value` for a POSIX shell unless it's made only of characters
+/// that never need quoting.
+fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+:,@%=".contains(c);
+
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Settings controlling how `clone_repo` fetches a repository.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct CloneOptions<'a> {
+    /// `key=value` pairs passed to git as `-c key=value`. Only a few keys have an
+    /// equivalent when cloning through `git2`, the rest are ignored with a warning.
+    pub git_config: Vec<(&'a str, &'a str)>,
+    /// How often a clone that fails on the network is retried
+    pub retry_policy: RetryPolicy,
+    /// Only fetch the git objects, leaving the working tree empty
+    pub no_checkout: bool,
+    /// Check out the newest commit at or before this time instead of the branch's tip,
+    /// as given on the command line and as Unix time
+    pub at: Option<(&'a str, i64)>,
+    /// Check out the newest commit whose message has a line matching this instead of the
+    /// branch's tip, searching back from `at` if that's given too
+    pub find_commit: Option<Regex>,
+    /// Check out (detached) whatever this branch, tag or commit resolves to instead of
+    /// cloning a single branch
+    pub rev: Option<&'a str>,
+    /// The private key SSH remotes are authenticated with, instead of looking for one
+    pub ssh_key: Option<&'a Path>,
+    /// What to do with SSH hosts that aren't in `known_hosts` yet
+    pub host_key_checking: HostKeyChecking,
+    /// The `known_hosts` to use instead of `~/.ssh/known_hosts`
+    pub known_hosts: Option<&'a Path>,
+    /// Accept any TLS certificate, as with `http.sslVerify=false`
+    pub insecure: bool,
+    /// A CA bundle to verify TLS certificates with instead of the system's
+    pub ca_cert: Option<&'a Path>,
+    /// A local clone whose objects are borrowed instead of fetched, as with
+    /// `git clone --reference`
+    pub reference: Option<&'a Path>,
+    /// Which of the remote's tags are fetched along with the branches
+    pub fetch_tags: FetchTags,
+}
+
+/// Which tags a clone or merge fetches, as with `--fetch-tags`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum FetchTags {
+    /// Only the tags on commits that are fetched anyway, like `git fetch` does
+    Auto,
+    /// Every tag, like `git clone` does
+    All,
+    /// No tags at all, so checking out a tag later needs a `git fetch --tags` first
+    None,
+}
+
+impl Default for FetchTags {
+    fn default() -> Self {
+        FetchTags::Auto
+    }
+}
+
+#[cfg(not(windows))]
+impl FetchTags {
+    fn autotag(self) -> ::git2::AutotagOption {
+        use git2::AutotagOption;
+
+        match self {
+            FetchTags::Auto => AutotagOption::Auto,
+            FetchTags::All => AutotagOption::All,
+            FetchTags::None => AutotagOption::None,
+        }
+    }
 }
 
 impl<'a> Get<'a> {
@@ -25,23 +246,197 @@ impl<'a> Get<'a> {
         let resource = subcommand.subcommand();
         let (resource, args) = (resource.0, resource.1.unwrap());
 
+        // Aliases arrive as external subcommands, which take any trailing arguments
+        // positionally, so anything after the alias is a mistake
+        let resource = match resource {
+            name if name.eq_ignore_ascii_case(CORE_NAME) => "core",
+            name if name.eq_ignore_ascii_case(RTS_NAME) => "rts",
+            name => name,
+        };
+        if (resource == "core" || resource == "rts") && args.is_present("") {
+            usage_and_exit!(subcommand);
+        }
+
         let save_path = subcommand.value_of("save-path");
         let branch = subcommand.value_of("branch").unwrap_or(DEFAULT_BRANCH);
 
         let force = subcommand.is_present("force");
 
-        match resource {
-            "core" => Ok(Get::new_core(save_path, branch, force, scaii_dir)),
-            "rts" => Ok(Get::new_rts(save_path, branch, force, scaii_dir)),
-            "backend" => Get::new_backend(
-                NameOrPath::try_from_path_or_name(save_path, args.value_of("name")).unwrap(),
-                branch,
-                force,
-                args.value_of("url").unwrap(),
-                scaii_dir,
-            ),
+        let mut get = match resource {
+            "core" => Get::new_core(save_path, branch, force, scaii_dir),
+            "rts" => Get::new_rts(save_path, branch, force, scaii_dir),
+            "backend" => {
+                let url = args.value_of("url").unwrap();
+                let name = match (save_path, args.value_of("name")) {
+                    (None, None) => match NameOrPath::name_from_url(url) {
+                        Some(name) => Some(name),
+                        None => bail!(
+                            "Cannot infer a backend name from '{}' (Hint: give one with '--name')",
+                            url
+                        ),
+                    },
+                    (_, name) => name,
+                };
+
+                let layout = match args.value_of("output-layout") {
+                    Some("flat") => OutputLayout::Flat,
+                    Some("by-owner") => OutputLayout::ByOwner,
+                    _ => OutputLayout::Nested,
+                };
+
+                Get::new_backend(
+                    NameOrPath::try_from_path_or_name(save_path, name).unwrap(),
+                    branch,
+                    force,
+                    url,
+                    layout,
+                    scaii_dir,
+                )?
+            }
             _ => usage_and_exit!(subcommand),
+        };
+
+        if let Some(prefix) = subcommand.value_of("checkout-path-prefix") {
+            get.path.push(util::parse_relative_dir("checkout path prefix", prefix)?);
+        }
+        get.merge = subcommand.is_present("merge");
+        get.only_if_missing = subcommand.is_present("only-if-missing");
+        if subcommand.is_present("show-changes") {
+            get.changes_limit = Some(if subcommand.is_present("changes-limit") {
+                value_t!(subcommand, "changes-limit", usize).unwrap_or_else(|e| e.exit())
+            } else {
+                DEFAULT_CHANGES_LIMIT
+            });
+        }
+        get.allow_dirty = subcommand.is_present("allow-dirty");
+        get.force_deps = subcommand.is_present("force-deps");
+        get.skip_existing_deps = subcommand.is_present("skip-existing-deps");
+        get.concurrent_deps = subcommand.value_of("concurrent-deps") != Some("no");
+        get.verbose = subcommand.is_present("verbose");
+        get.resolve_only = subcommand.is_present("resolve-only");
+        get.tarball = subcommand.is_present("tarball");
+        get.keep_archives = subcommand.is_present("keep-archive");
+        get.pack_objects = subcommand.is_present("pack-objects");
+        if subcommand.is_present("verify-signature") {
+            get.verify_signature = Some(subcommand.value_of("gpg-keyring").map(Path::new));
+        }
+        // Global, so it may have been given after the resource instead
+        let dir_mode = subcommand.value_of("dir-permissions").or(args.value_of("dir-permissions"));
+        if let Some(mode) = dir_mode {
+            get.dir_mode = Some(util::parse_dir_mode(mode)?);
+        }
+        let depth_limit = subcommand.value_of("depth-limit").or(args.value_of("depth-limit"));
+        if let Some(limit) = depth_limit {
+            get.depth_limit = util::parse_depth_limit(limit)?;
+        }
+        if subcommand.is_present("from-mirror") {
+            let name = match resource {
+                "core" => CORE_NAME,
+                "rts" => RTS_NAME,
+                _ => match args.value_of("name")
+                    .or_else(|| NameOrPath::name_from_url(args.value_of("url").unwrap()))
+                {
+                    Some(name) => name,
+                    None => bail!("Cannot tell which mirror to use (Hint: give a '--name')"),
+                },
+            };
+            get.mirror = Some(scaii_dir.join("mirror").join(format!("{}.git", name)));
+        }
+
+        if let Some(mirror) = subcommand.value_of("dep-mirror") {
+            get.dep_mirror = Some(parse_dep_mirror(mirror)?);
+        }
+        if let Some(dir) = subcommand.value_of("dep-extract-dir") {
+            get.dep_dir = util::parse_relative_dir("dependency extract directory", dir)?;
+        }
+        get.clone_options.no_checkout = subcommand.is_present("no-checkout");
+        get.clone_options.rev = subcommand.value_of("ref");
+        get.clone_options.fetch_tags = match subcommand.value_of("fetch-tags") {
+            Some("all") => FetchTags::All,
+            Some("none") => FetchTags::None,
+            _ => FetchTags::Auto,
+        };
+        if let Some(at) = subcommand.value_of("at") {
+            get.clone_options.at = Some((at, parse_timestamp(at)?));
+        }
+        if let Some(pattern) = subcommand.value_of("find-commit") {
+            get.clone_options.find_commit = Some(Regex::new(pattern)?);
+        }
+
+        if let Some(policy) = subcommand.value_of("retry-policy") {
+            let policy = RetryPolicy::parse(policy)?;
+            get.curl_options.connect = policy.clone();
+            get.curl_options.transfer = policy.clone();
+            get.clone_options.retry_policy = policy;
+        }
+
+        if let Some(statuses) = subcommand.value_of("retry-on-http") {
+            get.curl_options.retry_on_http = RetryStatuses::parse(statuses)?;
+        }
+
+        // The older per-class flags only override how many attempts are made
+        if subcommand.is_present("connect-retries") {
+            let retries = value_t!(subcommand, "connect-retries", u32).unwrap_or_else(|e| e.exit());
+            get.curl_options.connect.attempts = retries.saturating_add(1);
+        }
+        if subcommand.is_present("transfer-retries") {
+            let retries = value_t!(subcommand, "transfer-retries", u32).unwrap_or_else(|e| e.exit());
+            get.curl_options.transfer.attempts = retries.saturating_add(1);
+        }
+
+        if let Some(configs) = subcommand.values_of("git-config") {
+            get.clone_options.git_config = configs
+                .map(parse_git_config)
+                .collect::<error::Result<_>>()?;
+        }
+
+        if let Some(ca_cert) = subcommand.value_of("cacert") {
+            let ca_cert = Path::new(ca_cert);
+            ensure!(ca_cert.is_file(), "CA bundle '{}' does not exist", ca_cert.display());
+
+            get.curl_options.ca_cert = Some(ca_cert.to_path_buf());
+            get.clone_options.ca_cert = Some(ca_cert);
+        }
+        get.curl_options.trace = subcommand.is_present("trace-curl");
+        get.curl_options.netrc = subcommand.is_present("auth-netrc");
+        if subcommand.is_present("insecure") {
+            get.curl_options.verify_tls = false;
+            get.clone_options.insecure = true;
+        }
+
+        if let Some(reference) = subcommand.value_of("reference") {
+            let reference = Path::new(reference);
+            reference_objects(reference)?;
+            get.clone_options.reference = Some(reference);
         }
+
+        if let Some(key) = subcommand.value_of("ssh-key") {
+            use std::fs;
+            use error::{ErrorKind, ResultExt};
+            use util::ssh;
+
+            let key = Path::new(key);
+            let contents = fs::read_to_string(key)
+                .chain_err(|| ErrorKind::CannotReadError(format!("{}", key.display())))?;
+            ensure!(
+                !ssh::is_locked_key(&contents),
+                "SSH key '{}' is locked with a passphrase (Hint: add it to the ssh-agent with \
+                 `ssh-add` and leave out --ssh-key instead)",
+                key.display()
+            );
+
+            get.clone_options.ssh_key = Some(key);
+        }
+        let checking = subcommand.value_of("strict-host-key-checking");
+        get.clone_options.host_key_checking = match checking {
+            Some("no") => HostKeyChecking::No,
+            Some("accept-new") => HostKeyChecking::AcceptNew,
+            _ => HostKeyChecking::Yes,
+        };
+        get.clone_options.known_hosts = subcommand.value_of("known-hosts").map(Path::new);
+
+        get.validate()?;
+        Ok(get)
     }
 
     pub fn new_core(
@@ -50,13 +445,8 @@ impl<'a> Get<'a> {
         force: bool,
         scaii_dir: &Path,
     ) -> Self {
-        Get {
-            path: NameOrPath::from_path_or_default(save_path, CORE_NAME).to_path_buf(scaii_dir),
-            url: CORE_URL,
-            branch: branch,
-            force,
-            is_core: true,
-        }
+        let path = NameOrPath::from_path_or_default(save_path, CORE_NAME).to_path_buf(scaii_dir);
+        Get::new(path, CORE_URL, branch, force, "core", scaii_dir)
     }
 
     pub fn new_rts(
@@ -65,13 +455,8 @@ impl<'a> Get<'a> {
         force: bool,
         scaii_dir: &Path,
     ) -> Self {
-        Get {
-            path: NameOrPath::from_path_or_default(save_path, RTS_NAME).to_path_buf(scaii_dir),
-            url: RTS_URL,
-            branch: branch,
-            force,
-            is_core: false,
-        }
+        let path = NameOrPath::from_path_or_default(save_path, RTS_NAME).to_path_buf(scaii_dir);
+        Get::new(path, RTS_URL, branch, force, "rts", scaii_dir)
     }
 
     pub fn new_backend(
@@ -79,6 +464,7 @@ impl<'a> Get<'a> {
         branch: &'a str,
         force: bool,
         url: &'a str,
+        layout: OutputLayout,
         scaii_dir: &Path,
     ) -> error::Result<Self> {
         if let NameOrPath::Name(ref name) = name_path {
@@ -90,55 +476,433 @@ impl<'a> Get<'a> {
             }
         }
 
-        Ok(Get {
-            path: name_path.to_path_buf(scaii_dir),
-            url: url,
-            branch: branch,
+        let depth_limit = match name_path {
+            NameOrPath::Name(_) => layout.depth_limit(),
+            NameOrPath::SavePath(_) => DEFAULT_DEPTH_LIMIT,
+        };
+        let path = name_path.layout_path(scaii_dir, layout, url);
+        let mut get = Get::new(path, url, branch, force, "backend", scaii_dir);
+        get.depth_limit = depth_limit;
+        Ok(get)
+    }
+
+    /// A get of `resource` from `url` into `path`, with everything the command line can
+    /// change left at its default.
+    fn new(
+        path: PathBuf,
+        url: &'a str,
+        branch: &'a str,
+        force: bool,
+        resource: &'static str,
+        scaii_dir: &Path,
+    ) -> Self {
+        Get {
+            path,
+            url,
+            branch,
             force,
-            is_core: false,
-        })
+            merge: false,
+            only_if_missing: false,
+            changes_limit: None,
+            allow_dirty: false,
+            force_deps: false,
+            skip_existing_deps: false,
+            concurrent_deps: true,
+            verbose: false,
+            dir_mode: None,
+            resolve_only: false,
+            tarball: false,
+            keep_archives: false,
+            pack_objects: false,
+            verify_signature: None,
+            dep_mirror: None,
+            dep_dir: PathBuf::from(DEP_EXTRACT_DIR),
+            mirror: None,
+            is_core: resource == "core",
+            resource,
+            scaii_dir: scaii_dir.to_path_buf(),
+            depth_limit: DEFAULT_DEPTH_LIMIT,
+            curl_options: CurlOptions::default(),
+            clone_options: CloneOptions::default(),
+        }
     }
 
-    pub fn get(mut self) -> error::Result<()> {
-        use std::fs;
+    pub fn get(mut self) -> error::Result<GetReport> {
         use fs2;
+        use verify::head_commit;
         use error::{ErrorKind, ResultExt};
 
-        if self.path.exists() && !self.force {
+        if self.clone_options.insecure {
+            log_eprintln!(
+                "Warning: TLS certificates are not being verified (--insecure), so anyone on \
+                 the network path can tamper with what is fetched"
+            );
+        }
+        if self.clone_options.host_key_checking == HostKeyChecking::No {
+            if let Some((host, _)) = util::ssh::ssh_host(self.url) {
+                log_eprintln!(
+                    "Warning: SSH host keys are not being checked (--strict-host-key-checking \
+                     no), so anyone on the network path can pose as '{}'",
+                    host
+                );
+            }
+        }
+        if let Some(reference) = self.clone_options.reference {
+            log_eprintln!(
+                "Warning: the clone will borrow objects from '{}', so it breaks if that \
+                 repository is moved or deleted",
+                reference.display()
+            );
+        }
+        #[cfg(not(windows))]
+        init_git2_tls(&self.clone_options)?;
+
+        // Checked before anything is touched, cloning from a missing mirror would fail anyway
+        let url = self.clone_url()?;
+
+        // Nothing is written at all, so none of the checks on the target apply
+        if self.resolve_only {
+            log_progress!("Resolving '{}' at '{}'", self.branch, url);
+            let commit = remote_ref_commit(&url, self.branch, &self.clone_options)
+                .chain_err(|| format!("Could not list the refs of '{}'", url))?;
+
+            return match commit {
+                Some(commit) => Ok(GetReport {
+                    resource: self.resource,
+                    path: self.path.clone(),
+                    commit: Some(commit),
+                    ..GetReport::default()
+                }),
+                None => bail!("Branch '{}' does not exist at '{}'", self.branch, url),
+            };
+        }
+
+        if self.only_if_missing && self.path.exists() {
+            let commit = self.existing_checkout()?;
+            log_progress!(
+                "'{}' is already installed at {}, not fetching it again",
+                self.path.display(),
+                commit
+            );
+
+            return Ok(GetReport {
+                resource: self.resource,
+                path: self.path.clone(),
+                commit: Some(commit),
+                skipped: true,
+                ..GetReport::default()
+            });
+        }
+
+        // Merging into a directory that isn't there yet is just a clone
+        let merge = self.merge && self.path.exists();
+
+        if merge {
+            // Nothing is deleted, a failed fetch leaves the old tree as it was. The checkout
+            // does overwrite local edits to tracked files though, so only do that if asked to
+            if !self.allow_dirty {
+                let dirty = has_local_changes(&self.path)
+                    .chain_err(|| format!("Could not check {} for changes", self.path.display()))?;
+                ensure!(
+                    !dirty,
+                    "{} has uncommitted changes that merging would overwrite (Hint: commit or \
+                     stash them, or rerun this command with '--allow-dirty' to discard them)",
+                    self.path.display()
+                );
+            }
+        } else if self.path.exists() && !self.force {
             bail!(
                 "Directory {} exists (Hint: rerun this command with '-f' to force overwrite)",
                 self.path.display()
             );
         } else if self.path.exists() && self.force {
+            // Make sure there's something to replace the old checkout with before deleting it.
+            // A `--ref` can be any revision, which can't be checked without fetching it
+            if self.clone_options.rev.is_none() {
+                let has_ref = remote_ref_commit(&url, self.branch, &self.clone_options)
+                    .chain_err(|| format!("Could not list the refs of '{}'", url))?
+                    .is_some();
+                ensure!(
+                    has_ref,
+                    "Branch '{}' does not exist at '{}', leaving {} untouched",
+                    self.branch,
+                    url,
+                    self.path.display()
+                );
+            }
+
+            util::check_removable(&self.path, &self.scaii_dir, self.depth_limit)?;
             fs2::remove_dir_all(&self.path)
                 .chain_err(|| ErrorKind::CannotCleanError(format!("{}", self.path.display())))?;
         }
 
-        fs::create_dir_all(&self.path)
+        util::create_dir_all(&self.path, self.dir_mode)
             .chain_err(|| ErrorKind::CannotCreateError(format!("{}", self.path.display())))?;
 
-        println!(
-            "Cloning git repository at '{}' into '{}'",
-            self.url,
-            self.path.display()
-        );
+        let mut report = GetReport {
+            resource: self.resource,
+            path: self.path.clone(),
+            ..GetReport::default()
+        };
 
-        clone_repo(&self.path, &*self.url, &*self.branch)?;
+        let rev = self.clone_options.rev.unwrap_or(self.branch);
+        let tarball = if self.tarball {
+            let tarball = github_tarball_url(&url, rev);
+            if tarball.is_none() {
+                report.warnings.push(format!(
+                    "'{}' is not on GitHub, so it was cloned instead of fetched as a tarball",
+                    url
+                ));
+            }
+            tarball
+        } else {
+            None
+        };
 
-        if self.is_core {
-            self.get_core_resources()
-                .chain_err(|| "Could not fetch core dependencies")
+        if let Some(tarball) = tarball {
+            log_progress!("Downloading '{}' into '{}'", tarball, self.path.display());
+            fetch_tarball(&self.path, &tarball, &self.curl_options, self.dir_mode, &mut report)?;
         } else {
-            Ok(())
+            log_progress!(
+                "{} git repository at '{}' into '{}'",
+                if merge { "Merging" } else { "Cloning" },
+                url,
+                self.path.display()
+            );
+
+            if merge {
+                report.previous_commit = head_commit(&self.path);
+                merge_repo(&self.path, &url, self.branch, &self.clone_options, &mut report)?;
+
+                let range = (report.previous_commit.as_ref(), report.commit.as_ref());
+                if let (Some(limit), (Some(old), Some(new))) = (self.changes_limit, range) {
+                    let (changes, count) = commit_log(&self.path, old, new, limit)
+                        .chain_err(|| "Could not list the commits the merge brought in")?;
+                    report.changes = changes;
+                    report.changes_count = count;
+                }
+            } else {
+                clone_repo(&self.path, &url, self.branch, &self.clone_options, &mut report)?;
+            }
+        }
+
+        if let Some(keyring) = self.verify_signature {
+            let verified =
+                verify_signature(&self.path, self.clone_options.rev, self.branch, keyring);
+            match verified {
+                Ok(object) => log_progress!("Verified the signature of {}", object),
+                // Nothing of a fresh clone that can't be trusted is left behind
+                Err(err) => {
+                    if !merge {
+                        // The signature failure is the error to report either way
+                        let removable =
+                            util::check_removable(&self.path, &self.scaii_dir, self.depth_limit);
+                        match removable {
+                            Ok(()) => {
+                                let _ = fs2::remove_dir_all(&self.path);
+                            }
+                            Err(refused) => log_eprintln!(
+                                "Warning: leaving the unverified clone at '{}' in place: {}",
+                                self.path.display(),
+                                refused
+                            ),
+                        }
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        // A tarball has no `.git` to pack
+        if self.pack_objects && !report.tarball {
+            log_progress!("Packing the objects of '{}'", self.path.display());
+            let (before, after) = pack_objects(&self.path).chain_err(|| {
+                format!("Could not pack the objects of {}", self.path.display())
+            })?;
+            log_progress!(
+                "Packed '{}', which took {} before and {} after",
+                self.path.join(".git").display(),
+                format_size(before),
+                format_size(after)
+            );
+        }
+
+        // Without a working tree there's nothing for the dependencies to go into
+        if self.is_core && !self.clone_options.no_checkout {
+            // The dependency versions are pinned here rather than by the repository, so after
+            // a merge they only need fetching again if the new checkout moved them
+            if merge && !self.force_deps && self.core_resources_present() {
+                log_progress!("Core dependencies are still in place, not fetching them again");
+            } else {
+                self.get_core_resources(&mut report)
+                    .chain_err(|| "Could not fetch core dependencies")?;
+            }
         }
+
+        Ok(report)
+    }
+
+    /// Checks that the URL is a URL or a local path and that the branch (and `--ref`, if
+    /// given) could name a ref, so a `Get` put together by hand fails here rather than
+    /// with a confusing error from git partway through the clone.
+    pub fn validate(&self) -> error::Result<()> {
+        use error::ErrorKind;
+
+        let url = self.url;
+        let valid_url = match url.find("://") {
+            Some(scheme) => {
+                let (scheme, rest) = (&url[..scheme], &url[scheme + 3..]);
+                !scheme.is_empty()
+                    && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                    && !rest.is_empty()
+                    && !url.contains(char::is_whitespace)
+            }
+            // Local paths (and scp-like SSH URLs) may well have spaces in them
+            None => !url.trim().is_empty() && url.trim() == url,
+        };
+        ensure!(valid_url, ErrorKind::InvalidUrl(url.to_string()));
+
+        for name in Some(self.branch).into_iter().chain(self.clone_options.rev) {
+            ensure!(
+                !name.is_empty() && !name.contains(char::is_whitespace),
+                ErrorKind::InvalidRef(name.to_string())
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Where the resource is cloned to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The URL the resource is fetched from, even when it's cloned from a local mirror.
+    pub fn url(&self) -> &'a str {
+        self.url
+    }
+
+    /// The branches at the URL (or mirror) this would clone from and the commits they're
+    /// at, sorted by name, without cloning anything.
+    pub fn list_branches(&self) -> error::Result<Vec<(String, String)>> {
+        use error::ResultExt;
+
+        #[cfg(not(windows))]
+        init_git2_tls(&self.clone_options)?;
+
+        let url = self.clone_url()?;
+        log_progress!("Listing the branches of '{}'", url);
+        let refs = remote_refs(&url, &self.clone_options)
+            .chain_err(|| format!("Could not list the refs of '{}'", url))?;
+
+        Ok(branch_commits(&refs))
+    }
+
+    /// What this clones from, which is the mirror if there is one.
+    fn clone_url(&self) -> error::Result<String> {
+        Ok(match self.mirror {
+            Some(ref mirror) => {
+                ensure!(
+                    is_bare_repo(mirror),
+                    "No bare repository at {} (Hint: create the mirror with \
+                     'git clone --mirror {} {}')",
+                    mirror.display(),
+                    self.url,
+                    mirror.display()
+                );
+                match mirror.to_str() {
+                    Some(mirror) => mirror.to_string(),
+                    None => bail!("Mirror path {} is not valid UTF-8", mirror.display()),
+                }
+            }
+            None => self.url.to_string(),
+        })
+    }
+
+    /// Describes each dependency `get_core_resources` would fetch: its version, the URL
+    /// it would come from and where it would be extracted, without fetching anything.
+    pub fn describe_core_resources(&self) -> String {
+        let viz = self.path.join(&self.dep_dir);
+
+        CORE_DEPENDENCIES
+            .iter()
+            .map(|dep| {
+                let url = match self.dep_mirror {
+                    Some(mirror) => mirror_url(dep.url, mirror),
+                    None => dep.url.to_string(),
+                };
+                format!(
+                    "{} {}\n    from: {}\n    into: {}\n",
+                    dep.name,
+                    dep.version,
+                    url,
+                    viz.join(dep.dir).display()
+                )
+            })
+            .collect()
+    }
+
+    /// The commit of the checkout already at the target, if it's everything this `Get`
+    /// would have fetched. Branches and tags can't be checked without going to the remote,
+    /// so only a `--ref` that is a commit hash is compared.
+    fn existing_checkout(&self) -> error::Result<String> {
+        use verify::head_commit;
+
+        let commit = match head_commit(&self.path) {
+            Some(commit) => commit,
+            None => bail!(
+                "{} exists but isn't a git checkout (Hint: rerun this command with '-f' to \
+                 replace it)",
+                self.path.display()
+            ),
+        };
+
+        if let Some(rev) = self.clone_options.rev {
+            let is_hash = rev.len() >= 4 && rev.chars().all(|c| c.is_ascii_hexdigit());
+            ensure!(
+                !is_hash || commit.starts_with(&*rev.to_lowercase()),
+                "{} is at {}, not {} (Hint: rerun this command with '-f' to replace it)",
+                self.path.display(),
+                commit,
+                rev
+            );
+        }
+
+        // A `--no-checkout` clone never gets its dependencies
+        let needs_deps = self.is_core && !self.clone_options.no_checkout;
+        ensure!(
+            !needs_deps || self.core_resources_present(),
+            "{} is missing core dependencies (Hint: rerun this command with '-f' to replace \
+             it)",
+            self.path.display()
+        );
+
+        Ok(commit)
+    }
+
+    /// Whether the visualization dependencies are where `get_core_resources` puts them.
+    fn core_resources_present(&self) -> bool {
+        core_resource_dirs(&self.path, &self.dep_dir).iter().all(|dir| dir.is_dir())
     }
 
-    pub fn get_core_resources(&mut self) -> error::Result<()> {
+    /// Fetches the core's visualization dependencies, adding the bytes downloaded to
+    /// `report.downloaded_bytes`. Both are fetched at the same time unless
+    /// `concurrent_deps` is off.
+    pub fn get_core_resources(&mut self, report: &mut GetReport) -> error::Result<()> {
+        use std::{panic, thread};
         use error::ResultExt;
 
+        let (closure_url, protobuf_url) = match self.dep_mirror {
+            Some(mirror) => (
+                mirror_url(CLOSURE_LIB_URL, mirror),
+                mirror_url(PROTOBUF_JS_URL, mirror),
+            ),
+            None => (CLOSURE_LIB_URL.to_string(), PROTOBUF_JS_URL.to_string()),
+        };
+
         // Ensures we can't forget to pop our modifications off the path
         let mut path = CdManager::new(&mut self.path);
-        path.push("viz/js");
+        path.push(&self.dep_dir);
 
         ensure!(
             path.as_ref().exists(),
@@ -146,72 +910,3075 @@ impl<'a> Get<'a> {
             path.as_ref().display(),
         );
 
-        let buf = Vec::with_capacity(CLOSURE_LIB_BYTES.max(PROTOBUF_JS_BYTES));
-        let mut buf = get_closure_lib(path.layer(), buf)
-            .chain_err(|| "Could not fetch Google Closure Library")?;
-        buf.clear();
-        get_protobuf_js(path.layer(), buf).chain_err(|| "Could not fetch protobuf_js")?;
+        let pool = BufferPool::new(CLOSURE_LIB_BYTES.max(PROTOBUF_JS_BYTES));
+        let verbose = self.verbose;
+        let kept_dir = if self.keep_archives {
+            Some(path.as_ref().join(KEPT_ARCHIVES_DIR))
+        } else {
+            None
+        };
+        let dir_mode = self.dir_mode;
+        let mut record = |dir, url: &str, download: Download| -> error::Result<()> {
+            if verbose {
+                print_redirects(url, &download.redirects);
+            }
+            if let Some(ref kept_dir) = kept_dir {
+                keep_archive(kept_dir, dir, &download.buf, dir_mode)
+                    .chain_err(|| format!("Could not keep the archive of {}", dir))?;
+            }
+
+            report.downloaded_bytes += download.buf.len() as u64;
+            report.dependency_urls.push((dir, download.effective_url));
+            pool.give(download.buf);
+
+            Ok(())
+        };
+
+        // Trusted as they are, however they got there
+        let skip_existing = self.skip_existing_deps;
+        let in_place = |dir: &Path| {
+            let in_place = skip_existing && util::has_entries(dir);
+            if in_place {
+                log_progress!("'{}' is already in place, not fetching it again", dir.display());
+            }
+            in_place
+        };
+
+        // Each fetch owns everything it needs, so that it can run on a thread of its own
+        let mut closure = {
+            let (mut dir, url) = (path.as_ref().to_path_buf(), closure_url.clone());
+            let (curl_options, dir_mode, clean) =
+                (self.curl_options.clone(), self.dir_mode, self.force_deps);
+            move |buf| {
+                get_closure_lib(CdManager::new(&mut dir), buf, &url, &curl_options, dir_mode, clean)
+                    .chain_err(|| format!("Could not fetch Google Closure Library from '{}'", url))
+            }
+        };
+        let mut protobuf = {
+            let (mut dir, url) = (path.as_ref().to_path_buf(), protobuf_url.clone());
+            let (curl_options, dir_mode) = (self.curl_options.clone(), self.dir_mode);
+            move |buf| {
+                get_protobuf_js(CdManager::new(&mut dir), buf, &url, &curl_options, dir_mode)
+                    .chain_err(|| format!("Could not fetch protobuf_js from '{}'", url))
+            }
+        };
+
+        let fetch_closure = !in_place(&path.join(CLOSURE_LIB_DIR));
+        let fetch_protobuf = !in_place(&path.join(PROTOBUF_JS_DIR));
+        if fetch_closure && fetch_protobuf && self.concurrent_deps {
+            let (buf, logging) = (pool.take(), util::log::current());
+            let closure = thread::spawn(move || {
+                logging.inherit();
+                closure(buf)
+            });
+            let protobuf = protobuf(pool.take());
+            let closure = closure.join().unwrap_or_else(|panic| panic::resume_unwind(panic));
+
+            record(CLOSURE_LIB_DIR, &closure_url, closure?)?;
+            record(PROTOBUF_JS_DIR, &protobuf_url, protobuf?)?;
+        } else {
+            // One at a time, the second download reuses the first one's buffer
+            if fetch_closure {
+                record(CLOSURE_LIB_DIR, &closure_url, closure(pool.take())?)?;
+            }
+            if fetch_protobuf {
+                record(PROTOBUF_JS_DIR, &protobuf_url, protobuf(pool.take())?)?;
+            }
+        }
 
         Ok(())
     }
 }
 
-fn get_closure_lib(mut path: CdManager, buf: Vec<u8>) -> error::Result<Vec<u8>> {
-    use util;
-    path.push("closure_library");
+/// Saves the downloaded archive of the dependency extracted to `dir` as `<dir>.zip` under
+/// `kept_dir`, printing its SHA-1 so the exact bytes can be matched up later.
+fn keep_archive(
+    kept_dir: &Path,
+    dir: &str,
+    buf: &[u8],
+    dir_mode: Option<u32>,
+) -> error::Result<()> {
+    use std::fs;
+    use util::sha1::{sha1, to_hex};
+
+    util::create_dir_all(kept_dir, dir_mode)?;
+    let archive = kept_dir.join(format!("{}.zip", dir));
+    fs::write(&archive, buf)?;
+
+    log_progress!("Kept '{}' (sha1 {})", archive.display(), to_hex(&sha1(buf)));
+    Ok(())
+}
+
+/// Prints the redirects a download of `url` followed, one per line.
+fn print_redirects(url: &str, redirects: &[String]) {
+    if redirects.is_empty() {
+        log_eprintln!("Downloaded '{}' without redirects", url);
+        return;
+    }
+
+    log_eprintln!("Downloaded '{}' through {} redirect(s):", url, redirects.len());
+    for redirect in redirects {
+        log_eprintln!("  -> {}", redirect);
+    }
+}
+
+/// Where `get_core_resources` puts each dependency of the core checked out at `core`, under
+/// its `dep_dir`.
+pub fn core_resource_dirs(core: &Path, dep_dir: &Path) -> [PathBuf; 2] {
+    let viz = core.join(dep_dir);
+
+    [viz.join(CLOSURE_LIB_DIR), viz.join(PROTOBUF_JS_DIR)]
+}
+
+/// Fetches and extracts the Closure Library, first deleting any old copy if `clean` is set
+/// instead of extracting over it.
+fn get_closure_lib(
+    mut path: CdManager,
+    buf: Vec<u8>,
+    url: &str,
+    curl_options: &CurlOptions,
+    dir_mode: Option<u32>,
+    clean: bool,
+) -> error::Result<Download> {
+    use fs2;
+    path.push(CLOSURE_LIB_DIR);
+
+    let download = util::curl(url, Some(buf), curl_options)?;
 
-    let buf = util::curl(CLOSURE_LIB_URL, Some(buf))?;
-    util::unzip(&buf, path.layer(), true)?;
+    // Only once the download worked, so a failed one doesn't lose the old copy
+    if clean && path.as_ref().exists() {
+        fs2::remove_dir_all(path.as_ref())?;
+    }
+    let options = UnzipOptions {
+        into: true,
+        dir_mode,
+        ..UnzipOptions::default()
+    };
+    util::unzip(&download.buf, path.layer(), &options, &AtomicBool::new(false))?;
 
-    Ok(buf)
+    Ok(download)
 }
 
-fn get_protobuf_js(mut path: CdManager, buf: Vec<u8>) -> error::Result<Vec<u8>> {
-    use util;
-    use std::fs;
+fn get_protobuf_js(
+    mut path: CdManager,
+    buf: Vec<u8>,
+    url: &str,
+    curl_options: &CurlOptions,
+    dir_mode: Option<u32>,
+) -> error::Result<Download> {
     use fs2;
 
-    let buf = util::curl(PROTOBUF_JS_URL, Some(buf))?;
-    util::unzip(&buf, path.layer(), false)?;
+    let download = util::curl(url, Some(buf), curl_options)?;
+    let options = UnzipOptions {
+        dir_mode,
+        ..UnzipOptions::default()
+    };
+    util::unzip(&download.buf, path.layer(), &options, &AtomicBool::new(false))?;
 
-    let mut curr_dir = path.clone_inner();
-    curr_dir.push("protobuf_js");
+    let curr_dir = path.join(PROTOBUF_JS_DIR);
 
     path.push("protobuf-3.5.1");
 
     path.push("js");
 
-    fs::rename(&path, curr_dir)?;
+    // Left over from an earlier or partial get, the archive has the whole tree. Renaming
+    // onto it would fail on some platforms and merge the two on others
+    if curr_dir.exists() {
+        fs2::remove_dir_all(&curr_dir)?;
+    }
+    util::move_path(path.as_ref(), &curr_dir)?;
 
     path.pop()?;
     fs2::remove_dir_all(path)?;
 
-    Ok(buf)
+    Ok(download)
 }
 
-#[cfg(windows)]
-fn clone_repo<P: AsRef<Path>>(target: P, url: &str, branch: &str) -> error::Result<()> {
-    use std::process::{Command, Stdio};
+/// The URL GitHub serves a `.tar.gz` of `rev` from, if `url` is a GitHub repository.
+fn github_tarball_url(url: &str, rev: &str) -> Option<String> {
+    let host = match url.find("://") {
+        Some(scheme) => {
+            let rest = &url[scheme + 3..];
+            &rest[..rest.find('/')?]
+        }
+        // scp-like, `git@github.com:team/my-backend.git`
+        None => match (url.find(':'), url.find('/')) {
+            (Some(colon), Some(slash)) if colon < slash => &url[..colon],
+            _ => return None,
+        },
+    };
+    let host = &host[host.rfind('@').map_or(0, |at| at + 1)..];
+    if !host.eq_ignore_ascii_case("github.com") {
+        return None;
+    }
 
-    Command::new("git")
-        .arg("clone")
-        .arg(url)
-        .arg("-b")
-        .arg(branch)
-        .arg(target.as_ref().to_str().unwrap())
-        .stdout(Stdio::inherit())
-        .output()?;
+    let owner = NameOrPath::owner_from_url(url)?;
+    let name = NameOrPath::name_from_url(url)?;
+    Some(format!("https://codeload.github.com/{}/{}/tar.gz/{}", owner, name, rev))
+}
+
+/// Downloads the GitHub tarball at `url` into `target`, taking the commit from the
+/// comment GitHub leaves in it.
+fn fetch_tarball(
+    target: &Path,
+    url: &str,
+    curl_options: &CurlOptions,
+    dir_mode: Option<u32>,
+    report: &mut GetReport,
+) -> error::Result<()> {
+    use flate2::read::GzDecoder;
+    use util::tar::TarReader;
+    use error::ResultExt;
+
+    let download = util::curl(url, None, curl_options)
+        .chain_err(|| format!("Could not download the tarball '{}'", url))?;
+    let mut tar = TarReader::new(GzDecoder::new(&download.buf[..]));
+    extract_tarball(&mut tar, target, dir_mode)
+        .chain_err(|| format!("Could not extract the tarball '{}'", url))?;
+
+    report.commit = tar.comment()
+        .filter(|commit| commit.len() == 40 && commit.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_string);
+    report.tarball = true;
 
     Ok(())
 }
 
-#[cfg(not(windows))]
-fn clone_repo<P: AsRef<Path>>(target: P, url: &str, branch: &str) -> error::Result<()> {
-    use git2::build::RepoBuilder;
+/// Writes everything in a GitHub tarball into `target`, without the `<repo>-<ref>/`
+/// directory it's all in. Nothing is written outside of `target`, including through a
+/// symlink the tarball made.
+fn extract_tarball<R: ::std::io::Read>(
+    tar: &mut ::util::tar::TarReader<R>,
+    target: &Path,
+    dir_mode: Option<u32>,
+) -> error::Result<()> {
+    use util::tar::{EntryKind, Extractor};
 
-    RepoBuilder::new()
-        .branch(branch)
-        .clone(url, target.as_ref())?;
+    let mut extractor = Extractor::new(target, dir_mode);
+    while let Some(entry) = tar.next_entry()? {
+        let relative = Path::new(entry.path.find('/').map_or("", |top| &entry.path[top + 1..]));
+        if relative.as_os_str().is_empty() && entry.kind == EntryKind::Dir {
+            continue;
+        }
+        extractor.extract(&entry, tar, relative, "the checkout")?;
+    }
 
     Ok(())
 }
+
+/// Checks a `--dep-mirror` base URL, returning it without any trailing `/`.
+fn parse_dep_mirror(mirror: &str) -> error::Result<&str> {
+    let base = mirror.trim_end_matches('/');
+    let host = base.find("://").map_or("", |scheme| &base[scheme + "://".len()..]);
+
+    ensure!(
+        (base.starts_with("http://") || base.starts_with("https://")) && !host.is_empty()
+            && !host.starts_with('/') && !base.contains(&['?', '#'][..]),
+        "Invalid dependency mirror '{}' (Note: expected an http(s) base URL such as \
+         'https://mirror.internal' or 'https://mirror.internal/github')",
+        mirror
+    );
+
+    Ok(base)
+}
+
+/// Moves `url` onto `mirror` by replacing its scheme and host, keeping the path.
+fn mirror_url(url: &str, mirror: &str) -> String {
+    let after_scheme = url.find("://").map_or(0, |scheme| scheme + "://".len());
+    let path = url[after_scheme..]
+        .find('/')
+        .map_or("", |path| &url[after_scheme + path..]);
+
+    format!("{}{}", mirror, path)
+}
+
+/// Splits a `--git-config` argument into its key and value.
+fn parse_git_config(config: &str) -> error::Result<(&str, &str)> {
+    let mut parts = config.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if key.contains('.') && !key.starts_with('.')
+            && !key.ends_with('.') && !key.contains(char::is_whitespace) =>
+        {
+            Ok((key, value))
+        }
+        _ => bail!(
+            "Invalid git config '{}' (Note: expected 'section.key=value', e.g. 'http.sslVerify=false')",
+            config
+        ),
+    }
+}
+
+/// Parses a git config boolean, which may be spelled any of the ways git accepts.
+#[cfg(not(windows))]
+fn parse_git_bool(value: &str) -> Option<bool> {
+    match &*value.to_lowercase() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// The commit `branch` names at the remote `url`, as a branch or else a tag, without
+/// cloning anything. `None` if the remote has no such branch or tag.
+fn remote_ref_commit(
+    url: &str,
+    branch: &str,
+    options: &CloneOptions,
+) -> error::Result<Option<String>> {
+    Ok(pick_ref_commit(&remote_refs(url, options)?, branch))
+}
+
+/// The `(ref, sha)` pairs the remote `url` advertises, without cloning anything.
+#[cfg(windows)]
+fn remote_refs(url: &str, options: &CloneOptions) -> error::Result<Vec<(String, String)>> {
+    let output = configured_git(options).arg("ls-remote").arg(url).output()?;
+    ensure!(output.status.success(), "git ls-remote failed with {}", output.status);
+
+    // Each line is `<sha>\t<ref>`
+    let refs: Vec<(String, String)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            match (fields.next(), fields.next()) {
+                (Some(sha), Some(name)) => Some((name.to_string(), sha.to_string())),
+                _ => None,
+            }
+        })
+        .collect();
+
+    Ok(refs)
+}
+
+/// The `(ref, sha)` pairs the remote `url` advertises, without cloning anything.
+#[cfg(not(windows))]
+fn remote_refs(url: &str, options: &CloneOptions) -> error::Result<Vec<(String, String)>> {
+    use std::{env, process};
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use git2::{Direction, Repository};
+    use fs2;
+
+    // Each call gets its own, for threads listing refs at the same time
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // Anonymous remotes need a repository to live in, so use a throwaway one
+    let call = CALLS.fetch_add(1, Ordering::SeqCst);
+    let scratch = env::temp_dir().join(format!(
+        "better-install-ls-remote-{}-{}",
+        process::id(),
+        call
+    ));
+
+    let ssh_failure = RefCell::new(None);
+    let refs = (|| -> error::Result<Vec<(String, String)>> {
+        let repo = Repository::init_bare(&scratch)?;
+        let mut remote = repo.remote_anonymous(url)?;
+        let callbacks = remote_callbacks(url, options, &ssh_failure, &mut Vec::new());
+        let connection = remote
+            .connect_auth(Direction::Fetch, Some(callbacks), None)
+            .map_err(|err| explain_ssh_failure(err, &ssh_failure))?;
+
+        let refs: Vec<(String, String)> = connection
+            .list()?
+            .iter()
+            .map(|remote_head| (remote_head.name().to_string(), remote_head.oid().to_string()))
+            .collect();
+        Ok(refs)
+    })();
+
+    // Best effort, a leftover directory in the temp dir is harmless
+    let _ = fs2::remove_dir_all(&scratch);
+
+    refs
+}
+
+/// The branches out of a remote's `(ref, sha)` pairs, as `(name, sha)` sorted by name.
+fn branch_commits(refs: &[(String, String)]) -> Vec<(String, String)> {
+    let mut branches: Vec<(String, String)> = refs
+        .iter()
+        .filter(|(name, _)| name.starts_with("refs/heads/"))
+        .map(|(name, sha)| (name["refs/heads/".len()..].to_string(), sha.clone()))
+        .collect();
+    branches.sort();
+
+    branches
+}
+
+/// Lists branches as a JSON array of `{"name":...,"commit":...}` objects.
+pub fn branches_to_json(branches: &[(String, String)]) -> String {
+    let branches: Vec<String> = branches
+        .iter()
+        .map(|(name, sha)| {
+            format!(
+                "{{\"name\":{},\"commit\":{}}}",
+                error::json_string(name),
+                error::json_string(sha)
+            )
+        })
+        .collect();
+
+    format!("[{}]", branches.join(","))
+}
+
+/// Picks what `branch` resolves to out of a remote's `(ref, sha)` pairs. Branches win over
+/// tags as with `git clone -b`, and annotated tags are peeled to the commit they tag.
+fn pick_ref_commit(refs: &[(String, String)], branch: &str) -> Option<String> {
+    let wanted = [
+        format!("refs/heads/{}", branch),
+        format!("refs/tags/{}^{{}}", branch),
+        format!("refs/tags/{}", branch),
+    ];
+
+    wanted
+        .iter()
+        .filter_map(|wanted| refs.iter().find(|(name, _)| name == wanted))
+        .map(|(_, sha)| sha.clone())
+        .next()
+}
+
+#[cfg(windows)]
+fn clone_repo<P: AsRef<Path>>(
+    target: P,
+    url: &str,
+    branch: &str,
+    options: &CloneOptions,
+    report: &mut GetReport,
+) -> error::Result<()> {
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    let policy = &options.retry_policy;
+    let mut retried = 0;
+
+    // The exit status doesn't say why git failed, so every failure is retried
+    loop {
+        let mut git = configured_git(options);
+        git.arg("clone");
+        if options.no_checkout {
+            git.arg("--no-checkout");
+        }
+        if let Some(reference) = options.reference {
+            git.arg("--reference").arg(reference);
+        }
+        // Any revision may be asked for, which is resolved once the remote's HEAD is cloned
+        if options.rev.is_none() {
+            git.arg("-b").arg(branch);
+        }
+        if options.fetch_tags != FetchTags::All {
+            git.arg("--no-tags");
+        }
+
+        let status = git.arg(url)
+            .arg(target.as_ref().to_str().unwrap())
+            .stdout(Stdio::inherit())
+            .output()?
+            .status;
+
+        if status.success() {
+            break;
+        } else if retried >= policy.retries() {
+            bail!("git clone failed with {}", status);
+        }
+
+        log_progress!("Clone of '{}' failed ({}), retrying", url, status);
+        thread::sleep(policy.delay(retried));
+        retried += 1;
+    }
+
+    // `git clone` takes every tag or none of them, following only the tags on what was
+    // cloned is left to a fetch after it
+    if options.fetch_tags == FetchTags::Auto {
+        let git = |git: &mut Command, args: &[&str]| -> error::Result<()> {
+            let status = git.arg("-C").arg(target.as_ref()).args(args).output()?.status;
+            ensure!(status.success(), "git {} failed with {}", args[0], status);
+            Ok(())
+        };
+        git(&mut Command::new("git"), &["config", "--unset", "remote.origin.tagOpt"])?;
+        git(&mut configured_git(options), &["fetch", "--quiet", "origin"])?;
+    }
+
+    if let Some(rev) = options.rev {
+        // Branches only exist as remote-tracking branches in a fresh clone
+        let resolved = vec![rev.to_string(), format!("origin/{}", rev)]
+            .into_iter()
+            .filter_map(|rev| {
+                let found = Command::new("git")
+                    .arg("-C")
+                    .arg(target.as_ref())
+                    .arg("rev-parse")
+                    .arg("--verify")
+                    .arg("--quiet")
+                    .arg(format!("{}^{{commit}}", rev))
+                    .output()
+                    .ok()?;
+                let commit = String::from_utf8_lossy(&found.stdout).trim().to_string();
+                if found.status.success() && !commit.is_empty() {
+                    Some(commit)
+                } else {
+                    None
+                }
+            })
+            .next();
+        let commit = match resolved {
+            Some(commit) => commit,
+            None => bail!(
+                "Unknown or ambiguous revision '{}' at '{}' (Hint: give more of the commit \
+                 hash, or the full ref such as 'refs/tags/{}')",
+                rev,
+                url,
+                rev
+            ),
+        };
+
+        detach_at(target.as_ref(), &commit, options.no_checkout)?;
+    }
+
+    if let Some((at, seconds)) = options.at {
+        let found = Command::new("git")
+            .arg("-C")
+            .arg(target.as_ref())
+            .arg("rev-list")
+            .arg("-1")
+            .arg(format!("--before=@{}", seconds))
+            .arg("HEAD")
+            .output()?;
+        let commit = String::from_utf8_lossy(&found.stdout).trim().to_string();
+        ensure!(
+            found.status.success() && !commit.is_empty(),
+            "No commit on '{}' was made at or before {}",
+            branch,
+            at
+        );
+
+        detach_at(target.as_ref(), &commit, options.no_checkout)?;
+    }
+
+    if let Some(ref pattern) = options.find_commit {
+        // Each commit is its hash on a line of its own, then the message
+        let log = Command::new("git")
+            .arg("-C")
+            .arg(target.as_ref())
+            .arg("log")
+            .arg(format!("--max-count={}", FIND_COMMIT_LIMIT))
+            .arg("--format=%H%n%B%x00")
+            .arg("HEAD")
+            .output()?;
+        ensure!(log.status.success(), "git log failed with {}", log.status);
+
+        let log = String::from_utf8_lossy(&log.stdout);
+        let found = log.split('\0')
+            .filter_map(|entry| {
+                let mut lines = entry.trim_start().splitn(2, '\n');
+                match (lines.next(), lines.next()) {
+                    (Some(commit), Some(message)) if message_matches(pattern, message) => {
+                        Some(commit.to_string())
+                    }
+                    _ => None,
+                }
+            })
+            .next();
+        let commit = match found {
+            Some(commit) => commit,
+            None => bail!(no_matching_commit(pattern, branch)),
+        };
+
+        detach_at(target.as_ref(), &commit, options.no_checkout)?;
+    }
+
+    let head = Command::new("git")
+        .arg("-C")
+        .arg(target.as_ref())
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()?;
+    if head.status.success() {
+        report.commit = Some(String::from_utf8_lossy(&head.stdout).trim().to_string());
+    }
+
+    Ok(())
+}
+
+/// Points `HEAD` at `commit` (detached), checking its tree out too unless there's no
+/// working tree to check it out into, in which case only `HEAD` has to move.
+#[cfg(windows)]
+fn detach_at(repo: &Path, commit: &str, no_checkout: bool) -> error::Result<()> {
+    use std::process::Command;
+
+    let mut git = Command::new("git");
+    git.arg("-C").arg(repo);
+    if no_checkout {
+        git.arg("update-ref").arg("--no-deref").arg("HEAD").arg(commit);
+    } else {
+        git.arg("checkout").arg("--quiet").arg("--detach").arg(commit);
+    }
+    let status = git.output()?.status;
+    ensure!(status.success(), "Could not check out {}: git exited with {}", commit, status);
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn clone_repo<P: AsRef<Path>>(
+    target: P,
+    url: &str,
+    branch: &str,
+    options: &CloneOptions,
+    report: &mut GetReport,
+) -> error::Result<()> {
+    use std::thread;
+    use std::cell::RefCell;
+    use git2::build::{CheckoutBuilder, RepoBuilder};
+    use git2::{ErrorClass, FetchOptions};
+
+    let policy = &options.retry_policy;
+    let mut retried = 0;
+    let mut warnings = Vec::new();
+    let ssh_failure = RefCell::new(None);
+    let reference = match options.reference {
+        Some(reference) => Some(reference_objects(reference)?),
+        None => None,
+    };
+
+    // libgit2 cleans up after a failed clone, so the target is empty for the next attempt
+    let repo = loop {
+        warnings.clear();
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(url, options, &ssh_failure, &mut warnings));
+        fetch_options.download_tags(options.fetch_tags.autotag());
+
+        // libgit2's own clone ignores that and always fetches every tag
+        let cloned = if reference.is_some() || options.fetch_tags != FetchTags::All {
+            let objects = reference.as_ref().map(PathBuf::as_path);
+            clone_by_hand(target.as_ref(), url, branch, objects, fetch_options, options)
+        } else {
+            let mut builder = RepoBuilder::new();
+            builder.fetch_options(fetch_options);
+            // Any revision may be asked for, which is resolved once the remote's HEAD
+            // is cloned
+            if options.rev.is_none() {
+                builder.branch(branch);
+            }
+            if options.no_checkout {
+                // A dry run is libgit2's `GIT_CHECKOUT_NONE`, which writes nothing to
+                // the tree
+                let mut checkout = CheckoutBuilder::new();
+                checkout.dry_run();
+                builder.with_checkout(checkout);
+            }
+
+            builder.clone(url, target.as_ref())
+        };
+
+        let err = match cloned {
+            Ok(repo) => break repo,
+            Err(err) => err,
+        };
+
+        // A rejected host or key fails the same way every time
+        let transient = [ErrorClass::Net, ErrorClass::Ssl, ErrorClass::Ssh].contains(&err.class())
+            && ssh_failure.borrow().is_none();
+        if !transient || retried >= policy.retries() {
+            return Err(explain_ssh_failure(err, &ssh_failure));
+        }
+
+        log_progress!("Clone of '{}' failed ({}), retrying", url, err);
+        thread::sleep(policy.delay(retried));
+        retried += 1;
+    };
+    report.warnings.extend(warnings);
+
+    if let Some(rev) = options.rev {
+        let commit = resolve_rev(&repo, rev, url)?;
+        detach_at(&repo, commit, options.no_checkout)?;
+    }
+
+    if let Some((at, seconds)) = options.at {
+        let commit = match commit_at(&repo, seconds)? {
+            Some(commit) => commit,
+            None => bail!("No commit on '{}' was made at or before {}", branch, at),
+        };
+
+        detach_at(&repo, commit, options.no_checkout)?;
+    }
+
+    if let Some(ref pattern) = options.find_commit {
+        let commit = match commit_with_message(&repo, pattern)? {
+            Some(commit) => commit,
+            None => bail!(no_matching_commit(pattern, branch)),
+        };
+
+        detach_at(&repo, commit, options.no_checkout)?;
+    }
+
+    report.commit = repo.head()?.target().map(|oid| oid.to_string());
+
+    Ok(())
+}
+
+/// Points `HEAD` at `commit` (detached), checking its tree out too unless there's no
+/// working tree to check it out into, in which case only `HEAD` has to move.
+#[cfg(not(windows))]
+fn detach_at(
+    repo: &::git2::Repository,
+    commit: ::git2::Oid,
+    no_checkout: bool,
+) -> error::Result<()> {
+    use git2::build::CheckoutBuilder;
+
+    if !no_checkout {
+        let commit = repo.find_commit(commit)?;
+        repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+    }
+    repo.set_head_detached(commit)?;
+
+    Ok(())
+}
+
+/// Checks the GPG signature of the tag `rev` names if it's an annotated tag, and of the
+/// commit checked out at `path` otherwise, returning what was checked. Without a `rev`,
+/// `branch` is taken for a tag if what's checked out isn't a branch.
+fn verify_signature(
+    path: &Path,
+    rev: Option<&str>,
+    branch: &str,
+    keyring: Option<&Path>,
+) -> error::Result<String> {
+    use error::{ErrorKind, ResultExt};
+    use util::gpg;
+
+    let (object, raw, is_tag) = signed_object(path, rev, branch)
+        .chain_err(|| format!("Could not read what is checked out at '{}'", path.display()))?;
+    let signed = if is_tag {
+        gpg::split_signed_tag(&raw)
+    } else {
+        gpg::split_signed_commit(&raw)
+    };
+
+    let signed = match signed {
+        Some(signed) => signed,
+        None => bail!(ErrorKind::SignatureVerificationFailed(object, "it isn't signed".into())),
+    };
+    let scratch = path.join(".git").join("better-install-signature.asc");
+    match gpg::gpgv(&signed, keyring, &scratch) {
+        Ok(()) => Ok(object),
+        Err(reason) => bail!(ErrorKind::SignatureVerificationFailed(object, reason)),
+    }
+}
+
+/// The raw object `verify_signature` checks, named for messages, and whether it's a tag.
+/// A tag is only returned if it tags, perhaps through other tags, the commit checked out,
+/// since its signature says nothing about anything else.
+#[cfg(not(windows))]
+fn signed_object(
+    path: &Path,
+    rev: Option<&str>,
+    branch: &str,
+) -> error::Result<(String, Vec<u8>, bool)> {
+    use git2::{ObjectType, Repository};
+
+    let repo = Repository::open(path)?;
+    let odb = repo.odb()?;
+    let head = repo.head()?;
+    let tag = match rev {
+        Some(rev) => Some(rev),
+        None if !head.is_branch() => Some(branch),
+        None => None,
+    };
+    let head = head.peel_to_commit()?.id();
+
+    if let Some(name) = tag {
+        if let Ok(tag) = repo.find_reference(&format!("refs/tags/{}", name)) {
+            if let Some(oid) = tag.target() {
+                let object = odb.read(oid)?;
+                if object.kind() == ObjectType::Tag {
+                    let mut tagged = repo.find_tag(oid)?;
+                    while tagged.target_type() == Some(ObjectType::Tag) {
+                        tagged = repo.find_tag(tagged.target_id())?;
+                    }
+                    ensure!(
+                        tagged.target_id() == head,
+                        "The tag '{}' doesn't tag the commit checked out, {} (Note: it tags {})",
+                        name,
+                        head,
+                        tagged.target_id()
+                    );
+                    return Ok((format!("tag '{}'", name), object.data().to_vec(), true));
+                }
+            }
+        }
+    }
+
+    let raw = odb.read(head)?.data().to_vec();
+    Ok((format!("commit {}", head), raw, false))
+}
+
+#[cfg(windows)]
+fn signed_object(
+    path: &Path,
+    rev: Option<&str>,
+    branch: &str,
+) -> error::Result<(String, Vec<u8>, bool)> {
+    use std::process::Command;
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| output.stdout)
+    };
+
+    let text = |output: Vec<u8>| String::from_utf8_lossy(&output).trim().to_string();
+    let head = match git(&["rev-parse", "HEAD"]) {
+        Some(head) => text(head),
+        None => bail!("Nothing is checked out"),
+    };
+    let tag = match rev {
+        Some(rev) => Some(rev),
+        None if git(&["symbolic-ref", "-q", "HEAD"]).is_none() => Some(branch),
+        None => None,
+    };
+
+    if let Some(name) = tag {
+        let tag = format!("refs/tags/{}", name);
+        let kind = git(&["cat-file", "-t", &tag]);
+        if kind.map(|kind| text(kind) == "tag") == Some(true) {
+            // `^{commit}` peels through any tags of tags
+            let tagged = git(&["rev-parse", &format!("{}^{{commit}}", tag)]).map(text);
+            ensure!(
+                tagged.as_ref() == Some(&head),
+                "The tag '{}' doesn't tag the commit checked out, {} (Note: it tags {})",
+                name,
+                head,
+                tagged.unwrap_or_else(|| "something other than a commit".to_string())
+            );
+            if let Some(raw) = git(&["cat-file", "tag", &tag]) {
+                return Ok((format!("tag '{}'", name), raw, true));
+            }
+        }
+    }
+
+    match git(&["cat-file", "commit", &head]) {
+        Some(raw) => Ok((format!("commit {}", head), raw, false)),
+        None => bail!("Could not read commit {}", head),
+    }
+}
+
+/// Where `clone_by_hand` points at the reference repository's commits while fetching
+#[cfg(not(windows))]
+const BORROWED_REFS: &str = "refs/better-install/reference/*";
+
+/// What `RepoBuilder::clone` does, except that the tags `fetch_options` asks for are
+/// fetched instead of all of them, and the objects in `objects`, if given, are used through
+/// `objects/info/alternates` rather than fetched again. libgit2 only tells the remote
+/// about commits that local refs point at, which a clone can't have before fetching, so
+/// the reference repository's refs are copied in for the fetch and deleted after it.
+#[cfg(not(windows))]
+fn clone_by_hand(
+    target: &Path,
+    url: &str,
+    branch: &str,
+    objects: Option<&Path>,
+    mut fetch_options: ::git2::FetchOptions,
+    options: &CloneOptions,
+) -> Result<::git2::Repository, ::git2::Error> {
+    use std::fs;
+    use git2::build::CheckoutBuilder;
+    use git2::{Error, Repository};
+
+    let cloned = Repository::init(target).and_then(|repo| {
+        if let Some(objects) = objects {
+            let info = repo.path().join("objects").join("info");
+            fs::create_dir_all(&info)
+                .and_then(|_| {
+                    fs::write(info.join("alternates"), format!("{}\n", objects.display()))
+                })
+                .map_err(|err| Error::from_str(&err.to_string()))?;
+
+            let reference = Repository::open(objects.parent().unwrap())?;
+            for (i, tip) in reference
+                .references()?
+                .filter_map(|reference| reference.ok()?.target())
+                .enumerate()
+            {
+                let name = BORROWED_REFS.replace('*', &i.to_string());
+                repo.reference(&name, tip, true, "borrowed from a --reference repository")?;
+            }
+        }
+
+        let fetched = repo.remote("origin", url)
+            .and_then(|mut remote| remote.fetch(&[], Some(&mut fetch_options), None));
+        for name in repo.references_glob(BORROWED_REFS)?.names() {
+            repo.find_reference(name?)?.delete()?;
+        }
+        fetched?;
+
+        // Any revision may be asked for, which is resolved by the caller
+        if options.rev.is_none() {
+            let tip = repo.find_reference(&format!("refs/remotes/origin/{}", branch))?
+                .peel_to_commit()?
+                .id();
+            let mut local = repo.branch(branch, &repo.find_commit(tip)?, true)?;
+            local.set_upstream(Some(&format!("origin/{}", branch)))?;
+            repo.set_head(&format!("refs/heads/{}", branch))?;
+
+            let mut checkout = CheckoutBuilder::new();
+            if options.no_checkout {
+                checkout.dry_run();
+            } else {
+                checkout.force();
+            }
+            repo.checkout_head(Some(&mut checkout))?;
+        }
+
+        Ok(repo)
+    });
+
+    // Leave nothing behind for the next attempt, as libgit2 does for a failed clone
+    if cloned.is_err() {
+        let _ = ::fs2::remove_dir_all(target.join(".git"));
+    }
+    cloned
+}
+
+/// The object directory of the repository at `path`, bare or not, for `--reference`.
+fn reference_objects(path: &Path) -> error::Result<PathBuf> {
+    let objects = [path.join(".git").join("objects"), path.join("objects")]
+        .iter()
+        .find(|objects| objects.is_dir() && objects.parent().unwrap().join("HEAD").is_file())
+        .cloned();
+
+    match objects {
+        Some(objects) => Ok(objects),
+        None => bail!(
+            "'{}' is not a git repository (Hint: --reference takes the path of an existing \
+             clone)",
+            path.display()
+        ),
+    }
+}
+
+/// The commit `rev` names in a fresh clone of `url`. Branches only exist as
+/// remote-tracking branches there, so `rev` is also tried under `origin/`.
+#[cfg(not(windows))]
+fn resolve_rev(repo: &::git2::Repository, rev: &str, url: &str) -> error::Result<::git2::Oid> {
+    use git2::{ErrorCode, ObjectType};
+    use error::ResultExt;
+
+    let object = match repo.revparse_single(rev) {
+        Err(ref err) if err.code() == ErrorCode::NotFound => {
+            repo.revparse_single(&format!("origin/{}", rev))
+        }
+        result => result,
+    };
+
+    match object.and_then(|object| object.peel(ObjectType::Commit)) {
+        Ok(commit) => Ok(commit.id()),
+        Err(ref err) if err.code() == ErrorCode::Ambiguous => bail!(
+            "Revision '{}' is ambiguous (Hint: give more of the commit hash, or the full ref \
+             such as 'refs/tags/{}')",
+            rev,
+            rev
+        ),
+        Err(err) => Err(err).chain_err(|| {
+            format!(
+                "Unknown revision '{}' at '{}' (Note: only branches, tags and the commits \
+                 on them are fetched)",
+                rev, url
+            )
+        }),
+    }
+}
+
+/// The newest commit reachable from `HEAD` whose committer time is at or before `at`.
+/// History is scanned in full since commit times needn't increase along it.
+#[cfg(not(windows))]
+fn commit_at(repo: &::git2::Repository, at: i64) -> error::Result<Option<::git2::Oid>> {
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+
+    let mut newest = None;
+    for oid in walk {
+        let oid = oid?;
+        let time = repo.find_commit(oid)?.time().seconds();
+
+        match newest {
+            Some((newest_time, _)) if newest_time >= time => {}
+            _ if time <= at => newest = Some((time, oid)),
+            _ => {}
+        }
+    }
+
+    Ok(newest.map(|(_, oid)| oid))
+}
+
+/// The newest of the last `FIND_COMMIT_LIMIT` commits reachable from `HEAD` whose message
+/// matches `pattern`, as `message_matches` does.
+#[cfg(not(windows))]
+fn commit_with_message(
+    repo: &::git2::Repository,
+    pattern: &Regex,
+) -> error::Result<Option<::git2::Oid>> {
+    use git2::Sort;
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TIME);
+    walk.push_head()?;
+
+    for oid in walk.take(FIND_COMMIT_LIMIT) {
+        let oid = oid?;
+        if message_matches(pattern, repo.find_commit(oid)?.message().unwrap_or("")) {
+            return Ok(Some(oid));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether a `--find-commit` pattern is found in any line of a commit message, each
+/// searched on its own without the whitespace around it, so `^` and `$` anchor to it.
+fn message_matches(pattern: &Regex, message: &str) -> bool {
+    message.lines().any(|line| pattern.is_match(line.trim()))
+}
+
+fn no_matching_commit(pattern: &Regex, branch: &str) -> String {
+    format!(
+        "No commit on '{}' has a message matching '{}' (Note: only the last {} commits are \
+         searched, and each line of the message is searched on its own)",
+        branch,
+        pattern.as_str(),
+        FIND_COMMIT_LIMIT
+    )
+}
+
+/// Parses an `--at` time, `YYYY-MM-DD` optionally followed by `THH:MM` or `THH:MM:SS`
+/// (a space works in place of the `T`, and a trailing `Z` is allowed), into Unix time.
+/// Times are always UTC, and a bare date means the very start of that day.
+fn parse_timestamp(timestamp: &str) -> error::Result<i64> {
+    let invalid = || -> error::Error {
+        format!(
+            "Invalid time '{}' (Note: expected UTC as 'YYYY-MM-DD', 'YYYY-MM-DDTHH:MM' or \
+             'YYYY-MM-DDTHH:MM:SS')",
+            timestamp
+        ).into()
+    };
+    let number = |part: &str, min: i64, max: i64| -> error::Result<i64> {
+        match part.parse() {
+            Ok(n) if part.len() >= 2 && n >= min && n <= max => Ok(n),
+            _ => Err(invalid()),
+        }
+    };
+
+    let trimmed = timestamp.trim_end_matches('Z');
+    let mut halves = trimmed.splitn(2, &['T', ' '][..]);
+    let (date, time) = (halves.next().unwrap_or(""), halves.next());
+
+    let date: Vec<_> = date.split('-').collect();
+    if date.len() != 3 || date[0].len() != 4 {
+        return Err(invalid());
+    }
+    let (year, month) = (number(date[0], 1970, 9999)?, number(date[1], 1, 12)?);
+    let day = number(date[2], 1, days_in_month(year, month))?;
+
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let time: Vec<_> = time.split(':').collect();
+        if time.len() < 2 || time.len() > 3 {
+            return Err(invalid());
+        }
+        seconds += number(time[0], 0, 23)? * 3_600 + number(time[1], 0, 59)? * 60;
+        if let Some(second) = time.get(2) {
+            seconds += number(second, 0, 59)?;
+        }
+    }
+
+    Ok(seconds)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a (proleptic Gregorian) date, after Howard Hinnant's
+/// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Whether `path` is a bare git repository, such as one made by `git clone --mirror`.
+#[cfg(windows)]
+fn is_bare_repo(path: &Path) -> bool {
+    use std::process::Command;
+
+    if !path.is_dir() {
+        return false;
+    }
+
+    Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("rev-parse")
+        .arg("--is-bare-repository")
+        .output()
+        .map(|output| output.status.success() && output.stdout.starts_with(b"true"))
+        .unwrap_or(false)
+}
+
+/// Whether `path` is a bare git repository, such as one made by `git clone --mirror`.
+#[cfg(not(windows))]
+fn is_bare_repo(path: &Path) -> bool {
+    use git2::Repository;
+
+    Repository::open(path)
+        .map(|repo| repo.is_bare())
+        .unwrap_or(false)
+}
+
+/// Whether any tracked file at `path` differs from `HEAD`, untracked files don't count.
+/// A directory that isn't a repository has nothing to lose.
+#[cfg(windows)]
+fn has_local_changes(path: &Path) -> error::Result<bool> {
+    use std::process::Command;
+
+    if !path.join(".git").exists() {
+        return Ok(false);
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("status")
+        .arg("--porcelain")
+        .arg("--untracked-files=no")
+        .output()?;
+    ensure!(
+        status.status.success(),
+        "git status failed with {}",
+        status.status
+    );
+
+    Ok(!status.stdout.is_empty())
+}
+
+/// Whether any tracked file at `path` differs from `HEAD`, untracked files don't count.
+/// A directory that isn't a repository has nothing to lose.
+#[cfg(not(windows))]
+fn has_local_changes(path: &Path) -> error::Result<bool> {
+    use git2::{Repository, StatusOptions};
+
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(false),
+    };
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+
+    let dirty = !repo.statuses(Some(&mut options))?.is_empty();
+
+    Ok(dirty)
+}
+
+/// Fetches `branch` from `url` into the existing directory `target` and checks it out over
+/// whatever is there, turning the directory into a repository first if it isn't one.
+/// Changed files are overwritten and new ones added, but nothing is deleted: untracked files
+/// are left alone, and so are files the new commit no longer has, which become untracked.
+#[cfg(windows)]
+fn merge_repo<P: AsRef<Path>>(
+    target: P,
+    url: &str,
+    branch: &str,
+    options: &CloneOptions,
+    report: &mut GetReport,
+) -> error::Result<()> {
+    use std::process::Command;
+
+    let git = |args: &[&str], with_config: bool| -> error::Result<Vec<u8>> {
+        let mut git = if with_config {
+            configured_git(options)
+        } else {
+            Command::new("git")
+        };
+
+        let output = git.arg("-C").arg(target.as_ref()).args(args).output()?;
+        ensure!(
+            output.status.success(),
+            "git {} failed with {}",
+            args[0],
+            output.status
+        );
+
+        Ok(output.stdout)
+    };
+
+    if !target.as_ref().join(".git").exists() {
+        git(&["init"], false)?;
+    }
+    // A fetch from a URL rather than a remote never follows tags
+    match options.fetch_tags {
+        FetchTags::All => git(&["fetch", "--tags", url, branch], true)?,
+        _ => git(&["fetch", "--no-tags", url, branch], true)?,
+    };
+
+    // The checkout deletes what HEAD has and the new commit doesn't, so that's put back
+    let has_head = Command::new("git")
+        .arg("-C")
+        .arg(target.as_ref())
+        .args(&["rev-parse", "--verify", "--quiet", "HEAD"])
+        .output()?
+        .status
+        .success();
+    let removed: Vec<PathBuf> = if has_head {
+        let names = git(
+            &["diff", "--name-only", "-z", "--no-renames", "--diff-filter=D", "HEAD", "FETCH_HEAD"],
+            false,
+        )?;
+        String::from_utf8_lossy(&names)
+            .split('\0')
+            .filter(|name| !name.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let kept = read_kept_files(target.as_ref(), &removed)?;
+    git(&["checkout", "-f", "-B", branch, "FETCH_HEAD"], false)?;
+    restore_kept_files(kept)?;
+
+    let head = git(&["rev-parse", "HEAD"], false)?;
+    report.commit = Some(String::from_utf8_lossy(&head).trim().to_string());
+
+    Ok(())
+}
+
+/// Fetches `branch` from `url` into the existing directory `target` and checks it out over
+/// whatever is there, turning the directory into a repository first if it isn't one.
+/// Changed files are overwritten and new ones added, but nothing is deleted: untracked files
+/// are left alone, and so are files the new commit no longer has, which become untracked.
+#[cfg(not(windows))]
+fn merge_repo<P: AsRef<Path>>(
+    target: P,
+    url: &str,
+    branch: &str,
+    options: &CloneOptions,
+    report: &mut GetReport,
+) -> error::Result<()> {
+    use std::cell::RefCell;
+    use git2::build::CheckoutBuilder;
+    use git2::{Delta, FetchOptions, Repository};
+
+    let repo = match Repository::open(target.as_ref()) {
+        Ok(repo) => repo,
+        Err(_) => Repository::init(target.as_ref())?,
+    };
+
+    {
+        let ssh_failure = RefCell::new(None);
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(
+            url,
+            options,
+            &ssh_failure,
+            &mut report.warnings,
+        ));
+        fetch_options.download_tags(options.fetch_tags.autotag());
+        repo.remote_anonymous(url)?
+            .fetch(&[branch], Some(&mut fetch_options), None)
+            .map_err(|err| explain_ssh_failure(err, &ssh_failure))?;
+    }
+
+    let commit = repo.revparse_single("FETCH_HEAD")?.peel_to_commit()?;
+
+    // The checkout deletes what HEAD has and the new commit doesn't, so that's put back
+    let removed: Vec<PathBuf> = match repo.head().and_then(|head| head.peel_to_tree()) {
+        Ok(old) => repo.diff_tree_to_tree(Some(&old), Some(&commit.tree()?), None)?
+            .deltas()
+            .filter(|delta| delta.status() == Delta::Deleted)
+            .filter_map(|delta| delta.old_file().path().map(Path::to_path_buf))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let kept = read_kept_files(target.as_ref(), &removed)?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+    restore_kept_files(kept)?;
+
+    // Going through the reference directly, since `Repository::branch` refuses to move
+    // the branch that's checked out
+    let refname = format!("refs/heads/{}", branch);
+    repo.reference(&refname, commit.id(), true, "better-install: merge")?;
+    repo.set_head(&refname)?;
+
+    report.commit = Some(commit.id().to_string());
+
+    Ok(())
+}
+
+/// A file `--merge` keeps although the checkout it's merging deletes it.
+enum KeptFile {
+    File(Vec<u8>, ::std::fs::Permissions),
+    Symlink(PathBuf),
+}
+
+/// Reads whichever of `paths` are under `target`, to be put back once a checkout deleted
+/// them. They're only source files, so holding them in memory is fine.
+fn read_kept_files(target: &Path, paths: &[PathBuf]) -> error::Result<Vec<(PathBuf, KeptFile)>> {
+    use std::fs;
+
+    let mut kept = Vec::new();
+    for path in paths {
+        let full = target.join(path);
+        let kind = match fs::symlink_metadata(&full) {
+            Ok(metadata) => metadata.file_type(),
+            Err(_) => continue,
+        };
+
+        if kind.is_symlink() {
+            kept.push((full.clone(), KeptFile::Symlink(fs::read_link(&full)?)));
+        } else if kind.is_file() {
+            let permissions = fs::metadata(&full)?.permissions();
+            kept.push((full.clone(), KeptFile::File(fs::read(&full)?, permissions)));
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Puts back what `read_kept_files` read, unless the checkout put something else there.
+fn restore_kept_files(kept: Vec<(PathBuf, KeptFile)>) -> error::Result<()> {
+    use std::fs;
+    use util::tar::symlink;
+
+    for (path, file) in kept {
+        if fs::symlink_metadata(&path).is_ok() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            util::create_dir_all(parent, None)?;
+        }
+
+        match file {
+            KeptFile::File(contents, permissions) => {
+                fs::write(&path, contents)?;
+                fs::set_permissions(&path, permissions)?;
+            }
+            KeptFile::Symlink(link) => symlink(&link.to_string_lossy(), &path)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// The short hash and summary of up to `limit` of the commits in `new` that aren't in
+/// `old`, newest first, along with how many there are in all.
+#[cfg(windows)]
+fn commit_log(
+    path: &Path,
+    old: &str,
+    new: &str,
+    limit: usize,
+) -> error::Result<(Vec<String>, usize)> {
+    use std::process::Command;
+
+    let log = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("log")
+        .arg("--format=%h %s")
+        .arg(format!("{}..{}", old, new))
+        .output()?;
+    ensure!(log.status.success(), "git log failed with {}", log.status);
+
+    let log = String::from_utf8_lossy(&log.stdout);
+    let lines: Vec<_> = log.lines().collect();
+    let changes = lines.iter().take(limit).map(|line| line.to_string()).collect();
+
+    Ok((changes, lines.len()))
+}
+
+/// The short hash and summary of up to `limit` of the commits in `new` that aren't in
+/// `old`, newest first, along with how many there are in all.
+#[cfg(not(windows))]
+fn commit_log(
+    path: &Path,
+    old: &str,
+    new: &str,
+    limit: usize,
+) -> error::Result<(Vec<String>, usize)> {
+    use git2::{Oid, Repository, Sort};
+
+    let repo = Repository::open(path)?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME);
+    walk.push(Oid::from_str(new)?)?;
+    // Gone after a force push upstream, in which case everything on `new` is new
+    if let Ok(old) = Oid::from_str(old).and_then(|old| repo.find_commit(old)) {
+        walk.hide(old.id())?;
+    }
+
+    let (mut changes, mut count) = (Vec::new(), 0);
+    for oid in walk {
+        let oid = oid?;
+        count += 1;
+        if changes.len() < limit {
+            let commit = repo.find_commit(oid)?;
+            let hash = oid.to_string();
+            changes.push(format!("{} {}", &hash[..7], commit.summary().unwrap_or("")));
+        }
+    }
+
+    Ok((changes, count))
+}
+
+/// Packs the loose objects of the checkout at `path` with `git gc`, returning the size of
+/// its `.git` before and after. libgit2 can't prune what a pack replaces, so this always
+/// runs `git`.
+fn pack_objects(path: &Path) -> error::Result<(u64, u64)> {
+    use std::process::Command;
+
+    let git_dir = path.join(".git");
+    let before = util::disk_size(&git_dir)?;
+
+    let gc = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("gc")
+        .arg("--quiet")
+        .output()
+        .map_err(|err| {
+            format!("Could not run git gc: {} (Hint: --pack-objects needs git installed)", err)
+        })?;
+    ensure!(
+        gc.status.success(),
+        "git gc failed with {}: {}",
+        gc.status,
+        String::from_utf8_lossy(&gc.stderr).trim()
+    );
+
+    Ok((before, util::disk_size(&git_dir)?))
+}
+
+/// Sizes in bytes, KiB, MiB or GiB, whichever keeps them short.
+fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// A `git` command with the `--git-config` settings, the SSH and TLS options in `options`
+/// applied.
+#[cfg(windows)]
+fn configured_git(options: &CloneOptions) -> ::std::process::Command {
+    use std::env;
+    use std::process::Command;
+
+    let mut git = Command::new("git");
+    for &(key, value) in &options.git_config {
+        git.arg("-c").arg(format!("{}={}", key, value));
+    }
+    if let Some(ca_cert) = options.ca_cert {
+        git.arg("-c").arg(format!("http.sslCAInfo={}", ca_cert.display()));
+    }
+    if options.insecure {
+        git.arg("-c").arg("http.sslVerify=false");
+    }
+    // Host key options are added to any `GIT_SSH_COMMAND` of the user's own
+    let mut ssh = match options.ssh_key {
+        Some(key) => format!("ssh -i \"{}\" -o IdentitiesOnly=yes", key.display()),
+        None => env::var("GIT_SSH_COMMAND").unwrap_or_else(|_| "ssh".to_string()),
+    };
+    ssh.push_str(&format!(
+        " -o StrictHostKeyChecking={}",
+        options.host_key_checking.ssh_option()
+    ));
+    if let Some(known_hosts) = options.known_hosts {
+        ssh.push_str(&format!(" -o UserKnownHostsFile=\"{}\"", known_hosts.display()));
+    }
+    git.env("GIT_SSH_COMMAND", ssh);
+
+    git
+}
+
+/// Has libgit2 verify TLS certificates against the `--cacert` bundle in `options`, if
+/// there is one.
+///
+/// libgit2 has OpenSSL load `SSL_CERT_FILE` once, when git2 is first used, so this must run
+/// before anything else in the process touches git2, or the bundle is silently ignored. The
+/// variable is only set while libgit2 is initialized, and put back right after, so it
+/// doesn't reach the `git`, `gpg` and other processes run later.
+#[cfg(not(windows))]
+fn init_git2_tls(options: &CloneOptions) -> error::Result<()> {
+    use std::env;
+
+    let ca_cert = match options.ca_cert {
+        Some(ca_cert) => ca_cert,
+        None => return Ok(()),
+    };
+
+    let previous = env::var_os("SSL_CERT_FILE");
+    env::set_var("SSL_CERT_FILE", ca_cert);
+    // Anything in git2 initializes libgit2 first
+    let initialized = ::git2::Config::new();
+    match previous {
+        Some(previous) => env::set_var("SSL_CERT_FILE", previous),
+        None => env::remove_var("SSL_CERT_FILE"),
+    }
+
+    initialized?;
+    Ok(())
+}
+
+/// Builds the `git2` callbacks equivalent to the `--git-config` settings in `options`,
+/// recording a warning for each setting that can't be honored.
+///
+/// For SSH remotes the callbacks also authenticate, and check the host key against
+/// `known_hosts` as `ssh` would. Why a host or all keys were rejected is left in
+/// `ssh_failure`, since libgit2 only reports that the callback failed.
+#[cfg(not(windows))]
+fn remote_callbacks<'cb>(
+    url: &str,
+    options: &CloneOptions,
+    ssh_failure: &'cb ::std::cell::RefCell<Option<String>>,
+    warnings: &mut Vec<String>,
+) -> ::git2::RemoteCallbacks<'cb> {
+    use git2::{Cred, CredentialType, RemoteCallbacks};
+    use util::ssh::{self, HostKey};
+
+    let mut callbacks = RemoteCallbacks::new();
+
+    for &(key, value) in &options.git_config {
+        match (&*key.to_lowercase(), parse_git_bool(value)) {
+            ("http.sslverify", Some(false)) => {
+                warnings.push("TLS certificate verification was disabled for the clone".to_string());
+                callbacks.certificate_check(|_, _| true);
+            }
+            ("http.sslverify", Some(true)) => {}
+            _ => warnings.push(format!(
+                "git config '{}={}' has no libgit2 equivalent and was ignored",
+                key, value
+            )),
+        }
+    }
+
+    // `Get::get` has already warned about this one
+    if options.insecure {
+        callbacks.certificate_check(|_, _| true);
+    }
+
+    // SSH remotes use the same callback for their host key, which is always checked
+    let (host, port) = match ssh::ssh_host(url) {
+        Some((host, port)) => (host.to_string(), port),
+        None => return callbacks,
+    };
+
+    let known_hosts = read_known_hosts(options.known_hosts);
+    let checking = options.host_key_checking;
+    let added_to = options.known_hosts.map(Path::to_path_buf);
+    let checked_host = host.clone();
+    callbacks.certificate_check(move |cert, _| {
+        let sha1 = cert.as_hostkey().and_then(|key| key.hash_sha1());
+        let found = sha1.map(|sha1| ssh::check_known_host(&known_hosts, &checked_host, port, sha1));
+        let reason = match (found, checking) {
+            (_, HostKeyChecking::No) | (Some(HostKey::Known), _) => return true,
+            (Some(HostKey::Changed), _) => format!(
+                "The host key of '{}' doesn't match any in known_hosts, refusing to connect \
+                 (Note: the key may have been replaced, or the connection intercepted)",
+                checked_host
+            ),
+            (Some(HostKey::Unknown), HostKeyChecking::AcceptNew) => {
+                let added_to = added_to.as_ref().map(PathBuf::as_path);
+                match add_known_host(&checked_host, port, sha1.unwrap(), added_to) {
+                    Ok(()) => return true,
+                    Err(err) => err,
+                }
+            }
+            (Some(HostKey::Unknown), _) => format!(
+                "'{}' is not in known_hosts (Hint: connect once with `ssh {}` to check and \
+                 add its host key, or pass --strict-host-key-checking accept-new)",
+                checked_host, checked_host
+            ),
+            (None, _) => format!("'{}' sent no SSH host key that can be checked", checked_host),
+        };
+
+        *ssh_failure.borrow_mut() = Some(reason);
+        false
+    });
+
+    // libgit2 asks again after every rejected key, so each is offered once
+    let mut keys = ssh_keys(options).into_iter();
+    callbacks.credentials(move |_, username, allowed| {
+        let username = username.unwrap_or("git");
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+
+        match keys.next() {
+            Some(SshKey::Agent) => Cred::ssh_key_from_agent(username),
+            Some(SshKey::File(key)) => {
+                let public = key.with_extension("pub");
+                let public = if public.is_file() { Some(&*public) } else { None };
+                Cred::ssh_key(username, public, &key, None)
+            }
+            None => {
+                *ssh_failure.borrow_mut() = Some(format!(
+                    "'{}' accepted none of the SSH keys tried for '{}' (Hint: pass the right \
+                     one with --ssh-key, or add it to the ssh-agent if it has a passphrase)",
+                    host, username
+                ));
+                Err(::git2::Error::from_str("no SSH keys left to try"))
+            }
+        }
+    });
+
+    callbacks
+}
+
+/// Turns a `git2` error into one saying why the SSH callbacks gave up, if they did.
+#[cfg(not(windows))]
+fn explain_ssh_failure(
+    err: ::git2::Error,
+    ssh_failure: &::std::cell::RefCell<Option<String>>,
+) -> error::Error {
+    match ssh_failure.borrow_mut().take() {
+        Some(reason) => error::Error::with_chain(err, reason),
+        None => err.into(),
+    }
+}
+
+#[cfg(not(windows))]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum SshKey {
+    Agent,
+    File(PathBuf),
+}
+
+/// The keys to offer an SSH remote, in order. That's only `--ssh-key` if it was given,
+/// and otherwise the `-i` key in `GIT_SSH_COMMAND`, the ssh-agent's keys and then the
+/// default keys in `~/.ssh` without a passphrase, the way `ssh` would look for them.
+#[cfg(not(windows))]
+fn ssh_keys(options: &CloneOptions) -> Vec<SshKey> {
+    use std::{env, fs};
+    use util::ssh;
+
+    if let Some(key) = options.ssh_key {
+        return vec![SshKey::File(key.to_path_buf())];
+    }
+
+    let mut keys = Vec::new();
+    if let Some(key) = env::var("GIT_SSH_COMMAND").ok().and_then(|cmd| ssh_command_identity(&cmd)) {
+        keys.push(SshKey::File(key));
+    }
+    keys.push(SshKey::Agent);
+
+    if let Some(home) = env::home_dir() {
+        for name in &["id_ed25519", "id_ecdsa", "id_rsa"] {
+            let key = home.join(".ssh").join(name);
+            if let Ok(contents) = fs::read_to_string(&key) {
+                if !ssh::is_locked_key(&contents) {
+                    keys.push(SshKey::File(key));
+                }
+            }
+        }
+    }
+
+    keys
+}
+
+/// The identity file an `ssh` command line selects with `-i`, as in `GIT_SSH_COMMAND`.
+#[cfg(not(windows))]
+fn ssh_command_identity(command: &str) -> Option<PathBuf> {
+    let mut args = command.split_whitespace().skip(1);
+
+    while let Some(arg) = args.next() {
+        let key = match arg {
+            "-i" => args.next()?,
+            _ if arg.starts_with("-i") => &arg[2..],
+            _ => continue,
+        };
+
+        return Some(PathBuf::from(key.trim_matches(&['"', '\''][..])));
+    }
+
+    None
+}
+
+/// The user's `known_hosts`, or `file` in its place.
+#[cfg(not(windows))]
+fn user_known_hosts(file: Option<&Path>) -> Option<PathBuf> {
+    use std::env;
+
+    match file {
+        Some(file) => Some(file.to_path_buf()),
+        None => env::home_dir().map(|home| home.join(".ssh").join("known_hosts")),
+    }
+}
+
+/// Both the user's and the system's `known_hosts`, one after the other, with `file`
+/// read instead of the user's if given.
+#[cfg(not(windows))]
+fn read_known_hosts(file: Option<&Path>) -> String {
+    use std::fs;
+
+    let mut files = vec![PathBuf::from("/etc/ssh/ssh_known_hosts")];
+    files.extend(user_known_hosts(file));
+
+    files
+        .iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Adds the key of a host seen for the first time to the user's `known_hosts`, or to
+/// `file`. libgit2 only gives the SHA-1 of the key, so `ssh-keyscan` fetches the key
+/// itself and the one with that hash is written. Why it couldn't be added is the error.
+#[cfg(not(windows))]
+fn add_known_host(
+    host: &str,
+    port: Option<u16>,
+    key_sha1: &[u8],
+    file: Option<&Path>,
+) -> Result<(), String> {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use util::ssh;
+
+    let file = match user_known_hosts(file) {
+        Some(file) => file,
+        None => return Err("There is no home directory to keep known_hosts in".to_string()),
+    };
+
+    let scanned = Command::new("ssh-keyscan")
+        .arg("-p")
+        .arg(port.unwrap_or(22).to_string())
+        .arg(host)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| {
+            format!(
+                "Could not run ssh-keyscan to add '{}' to known_hosts: {} (Hint: is OpenSSH \
+                 installed?)",
+                host, err
+            )
+        })?;
+    let scanned = String::from_utf8_lossy(&scanned.stdout);
+    let line = match ssh::keyscan_line(&scanned, key_sha1) {
+        Some(line) => line,
+        None => {
+            return Err(format!(
+                "'{}' showed ssh-keyscan a different host key than the clone, refusing to \
+                 add it to known_hosts",
+                host
+            ))
+        }
+    };
+
+    // A retried clone sees the host as new again
+    let existing = fs::read_to_string(&file).unwrap_or_default();
+    if existing.lines().any(|known| known.trim() == line) {
+        return Ok(());
+    }
+    let separator = if existing.is_empty() || existing.ends_with('\n') { "" } else { "\n" };
+    file.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(&file))
+        .and_then(|mut known_hosts| writeln!(known_hosts, "{}{}", separator, line))
+        .map_err(|err| format!("Could not add '{}' to '{}': {}", host, file.display(), err))?;
+
+    log_eprintln!("Warning: permanently added the host key of '{}' to '{}'", host, file.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{branch_commits, branches_to_json, get_protobuf_js, keep_archive, mirror_url,
+                parse_dep_mirror, parse_git_config, parse_timestamp, pick_ref_commit, shell_quote,
+                Get, GetReport};
+    #[cfg(not(windows))]
+    use super::{clone_repo, commit_at, has_local_changes, is_bare_repo, merge_repo,
+                reference_objects, remote_ref_commit, ssh_command_identity, CloneOptions,
+                BORROWED_REFS};
+    #[cfg(not(windows))]
+    use std::path::Path;
+
+    #[test]
+    fn git_config_valid() {
+        assert_eq!(
+            parse_git_config("http.sslVerify=false").unwrap(),
+            ("http.sslVerify", "false")
+        );
+        assert_eq!(
+            parse_git_config("http.extraHeader=A: b=c").unwrap(),
+            ("http.extraHeader", "A: b=c")
+        );
+        assert_eq!(parse_git_config("core.askPass=").unwrap(), ("core.askPass", ""));
+    }
+
+    #[test]
+    fn dep_mirror_rewrite() {
+        use constants::{CLOSURE_LIB_URL, PROTOBUF_JS_URL};
+
+        let mirror = parse_dep_mirror("https://mirror.internal/").unwrap();
+        assert_eq!(
+            mirror_url(CLOSURE_LIB_URL, mirror),
+            "https://mirror.internal/google/closure-library/archive/v20171112.zip"
+        );
+
+        let mirror = parse_dep_mirror("http://mirror.internal:8080/github").unwrap();
+        assert_eq!(
+            mirror_url(PROTOBUF_JS_URL, mirror),
+            "http://mirror.internal:8080/github/google/protobuf/releases/download/v3.5.1/\
+             protobuf-js-3.5.1.zip"
+        );
+    }
+
+    #[test]
+    fn dep_mirror_invalid() {
+        assert!(parse_dep_mirror("mirror.internal").is_err());
+        assert!(parse_dep_mirror("ftp://mirror.internal").is_err());
+        assert!(parse_dep_mirror("https://").is_err());
+        assert!(parse_dep_mirror("https:///path").is_err());
+        assert!(parse_dep_mirror("https://mirror.internal/?token=1").is_err());
+    }
+
+    #[test]
+    fn protobuf_js_replaces_old_copy() {
+        use std::fs;
+        use fs2;
+        use util::{CdManager, CurlOptions};
+        use util::fixture::{temp_dir, zip_fixture, Fixture, FixtureServer};
+
+        let archive = |message: &[u8], extra: &str| {
+            zip_fixture(&[
+                ("protobuf-3.5.1/", b""),
+                ("protobuf-3.5.1/README.md", b"readme"),
+                ("protobuf-3.5.1/js/", b""),
+                ("protobuf-3.5.1/js/message.js", message),
+                (extra, b""),
+            ])
+        };
+        let server = FixtureServer::start(vec![
+            Fixture::ok(archive(b"v1", "protobuf-3.5.1/js/old.js")),
+            Fixture::ok(archive(b"v2", "protobuf-3.5.1/js/new.js")),
+        ]);
+
+        let mut dir = temp_dir("protobuf-js-twice");
+        for _ in 0..2 {
+            get_protobuf_js(
+                CdManager::new(&mut dir),
+                Vec::new(),
+                &server.url("/protobuf-js-3.5.1.zip"),
+                &CurlOptions::default(),
+                None,
+            ).unwrap();
+        }
+
+        let protobuf_js = dir.join("protobuf_js");
+        assert_eq!(fs::read(protobuf_js.join("message.js")).unwrap(), b"v2");
+        assert!(protobuf_js.join("new.js").exists());
+        assert!(!protobuf_js.join("old.js").exists());
+        assert!(!dir.join("protobuf-3.5.1").exists());
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn core_dependency_table() {
+        use std::path::Path;
+        use constants::{CORE_DEPENDENCIES, DEFAULT_BRANCH};
+
+        // The versions are written out separately, make sure they can't drift from the URLs
+        for dep in &CORE_DEPENDENCIES {
+            assert!(dep.url.contains(dep.version), "{} doesn't match its URL", dep.name);
+        }
+
+        let mut get = Get::new_core(Some("/scaii/SCAII"), DEFAULT_BRANCH, false, Path::new("/"));
+        get.dep_mirror = Some("https://mirror.internal");
+        let listing = get.describe_core_resources();
+
+        assert!(listing.contains("Google Closure Library v20171112\n"));
+        assert!(listing.contains("from: https://mirror.internal/google/closure-library/"));
+        assert!(listing.contains(&format!(
+            "into: {}",
+            Path::new("/scaii/SCAII/viz/js").join("protobuf_js").display()
+        )));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_timestamp("2021-06-01").unwrap(), 1_622_505_600);
+        assert_eq!(parse_timestamp("2021-06-01T12:30").unwrap(), 1_622_550_600);
+        assert_eq!(parse_timestamp("2021-06-01 12:30:15Z").unwrap(), 1_622_550_615);
+        assert_eq!(parse_timestamp("2020-02-29").unwrap(), 1_582_934_400);
+
+        assert!(parse_timestamp("2021-02-29").is_err());
+        assert!(parse_timestamp("2021-13-01").is_err());
+        assert!(parse_timestamp("2021-6-1").is_err());
+        assert!(parse_timestamp("21-06-01").is_err());
+        assert!(parse_timestamp("2021-06-01T25:00").is_err());
+        assert!(parse_timestamp("2021-06-01T12").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn ssh_command_identities() {
+        use std::path::PathBuf;
+
+        assert_eq!(
+            ssh_command_identity("ssh -i ~/.ssh/deploy -o IdentitiesOnly=yes"),
+            Some(PathBuf::from("~/.ssh/deploy"))
+        );
+        assert_eq!(
+            ssh_command_identity("ssh -o IdentitiesOnly=yes -i\"/keys/ci\""),
+            Some(PathBuf::from("/keys/ci"))
+        );
+        assert_eq!(ssh_command_identity("ssh -v"), None);
+        assert_eq!(ssh_command_identity("ssh -i"), None);
+    }
+
+    #[test]
+    fn report_as_env() {
+        use std::path::PathBuf;
+
+        let mut report = GetReport {
+            resource: "core",
+            path: PathBuf::from("/home/me/.scaii/git/SCAII"),
+            commit: Some("abc123".to_string()),
+            ..GetReport::default()
+        };
+        assert_eq!(
+            report.to_env(),
+            "SCAII_CORE_PATH=/home/me/.scaii/git/SCAII\nSCAII_CORE_COMMIT=abc123\n"
+        );
+
+        report.dependency_urls = vec![("protobuf_js", "https://cdn.example.com/js.zip".to_string())];
+        assert!(
+            report
+                .to_env()
+                .ends_with("SCAII_CORE_PROTOBUF_JS_URL=https://cdn.example.com/js.zip\n")
+        );
+
+        report.resource = "backend";
+        report.path = PathBuf::from("/home/me/my backend");
+        report.commit = None;
+        report.dependency_urls.clear();
+        assert_eq!(report.to_env(), "SCAII_BACKEND_PATH='/home/me/my backend'\n");
+    }
+
+    #[test]
+    fn report_as_json() {
+        use std::path::PathBuf;
+        use std::time::Duration;
+
+        let report = GetReport {
+            resource: "core",
+            path: PathBuf::from("C:\\Users\\me\\SCAII"),
+            commit: Some("abc123".to_string()),
+            downloaded_bytes: 10,
+            dependency_urls: vec![("protobuf_js", "https://cdn.example.com/js.zip".to_string())],
+            warnings: vec!["said \"hi\"".to_string()],
+            ..GetReport::default()
+        };
+        assert_eq!(
+            report.to_json(Duration::from_millis(1_500)),
+            "{\"status\":\"ok\",\"resource\":\"core\",\"path\":\"C:\\\\Users\\\\me\\\\SCAII\",\
+             \"commit\":\"abc123\",\"previous_commit\":null,\"skipped\":false,\"tarball\":false,\
+             \"downloaded_bytes\":10,\"dependencies\":[{\"dir\":\"protobuf_js\",\
+             \"url\":\"https://cdn.example.com/js.zip\"}],\"changes\":[],\"changes_count\":0,\
+             \"warnings\":[\"said \\\"hi\\\"\"],\"elapsed_ms\":1500}"
+        );
+    }
+
+    #[test]
+    fn shell_quoting() {
+        assert_eq!(shell_quote("/plain/path-1.0"), "/plain/path-1.0");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's $HOME"), "'it'\\''s $HOME'");
+    }
+
+    #[test]
+    fn archives_are_kept() {
+        use std::fs;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("keep-archive");
+        let kept = root.join(".downloads");
+        keep_archive(&kept, "closure_library", b"zip bytes", None).unwrap();
+        keep_archive(&kept, "closure_library", b"newer", None).unwrap();
+
+        assert_eq!(fs::read(kept.join("closure_library.zip")).unwrap(), b"newer");
+    }
+
+    #[test]
+    fn ref_commit_choice() {
+        let refs = |names: &[(&str, &str)]| -> Vec<(String, String)> {
+            names.iter().map(|&(name, sha)| (name.to_string(), sha.to_string())).collect()
+        };
+        let listed = refs(&[
+            ("HEAD", "a1"),
+            ("refs/heads/master", "a1"),
+            ("refs/heads/v1", "b2"),
+            ("refs/tags/v1", "c3"),
+            ("refs/tags/v2", "d4"),
+            ("refs/tags/v2^{}", "e5"),
+            ("refs/tags/v3", "f6"),
+        ]);
+
+        assert_eq!(pick_ref_commit(&listed, "master"), Some("a1".to_string()));
+        assert_eq!(pick_ref_commit(&listed, "v1"), Some("b2".to_string()));
+        assert_eq!(pick_ref_commit(&listed, "v2"), Some("e5".to_string()));
+        assert_eq!(pick_ref_commit(&listed, "v3"), Some("f6".to_string()));
+        assert_eq!(pick_ref_commit(&listed, "HEAD"), None);
+        assert_eq!(pick_ref_commit(&listed, "v4"), None);
+    }
+
+    #[test]
+    fn branch_listing() {
+        let listed: Vec<(String, String)> = [
+            ("HEAD", "a1"),
+            ("refs/heads/master", "a1"),
+            ("refs/heads/feature/\"x\"", "b2"),
+            ("refs/tags/master", "c3"),
+        ].iter()
+            .map(|&(name, sha)| (name.to_string(), sha.to_string()))
+            .collect();
+
+        let branches = branch_commits(&listed);
+        let names: Vec<&str> = branches.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(names, vec!["feature/\"x\"", "master"]);
+        assert_eq!(
+            branches_to_json(&branches),
+            r#"[{"name":"feature/\"x\"","commit":"b2"},{"name":"master","commit":"a1"}]"#
+        );
+        assert_eq!(branches_to_json(&[]), "[]");
+    }
+
+    #[test]
+    fn size_formatting() {
+        use super::format_size;
+
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(300 * 1024 * 1024), "300.0 MiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024 * 1024), "5120.0 GiB");
+    }
+
+    #[test]
+    fn git_config_invalid() {
+        assert!(parse_git_config("http.sslVerify").is_err());
+        assert!(parse_git_config("sslVerify=false").is_err());
+        assert!(parse_git_config("=false").is_err());
+        assert!(parse_git_config(".sslVerify=false").is_err());
+        assert!(parse_git_config("http. sslVerify=false").is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn remote_ref_precheck() {
+        use std::env;
+        use git2::{Repository, Signature};
+        use fs2;
+
+        let origin = env::temp_dir().join("better-install-test-remote-has-ref");
+        let _ = fs2::remove_dir_all(&origin);
+
+        let branch = {
+            let repo = Repository::init(&origin).unwrap();
+            let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+            let signature = Signature::now("test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+                .unwrap();
+
+            let head = repo.head().unwrap();
+            head.shorthand().unwrap().to_string()
+        };
+
+        let url = origin.to_str().unwrap();
+        let options = CloneOptions::default();
+        assert!(remote_ref_commit(url, &branch, &options).unwrap().is_some());
+        assert_eq!(remote_ref_commit(url, "no-such-branch", &options).unwrap(), None);
+
+        fs2::remove_dir_all(&origin).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn remote_refs_concurrently() {
+        use std::thread;
+        use fs2;
+        use util::fixture::temp_dir;
+        use super::remote_refs;
+
+        let root = temp_dir("ls-remote-threads");
+        let (branch, head) = origin_repo(&root, "contents");
+
+        // Each listing has its own scratch repository, so none removes another's
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let url = root.to_str().unwrap().to_string();
+                thread::spawn(move || remote_refs(&url, &CloneOptions::default()).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            let refs = thread.join().unwrap();
+            assert!(refs.contains(&(format!("refs/heads/{}", branch), head.clone())));
+        }
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    /// Creates a repository at `path` with a single commit adding `tracked.txt`,
+    /// returning its branch and the commit.
+    #[cfg(not(windows))]
+    fn origin_repo(path: &Path, contents: &str) -> (String, String) {
+        use std::fs;
+        use git2::{Repository, Signature};
+
+        let repo = Repository::init(path).unwrap();
+        fs::write(path.join("tracked.txt"), contents).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let head = repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+            .unwrap();
+
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+        (branch, head.to_string())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn signed_tag_must_tag_head() {
+        use fs2;
+        use git2::{Oid, Repository, Signature};
+        use util::fixture::temp_dir;
+        use super::signed_object;
+
+        let root = temp_dir("signed-tag");
+        let (branch, first) = origin_repo(&root, "first");
+        let repo = Repository::open(&root).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let first = repo.find_object(Oid::from_str(&first).unwrap(), None).unwrap();
+        let tag = repo.tag("v1", &first, &signature, "v1", false).unwrap();
+        let tag = repo.find_object(tag, None).unwrap();
+        repo.tag("v1-again", &tag, &signature, "v1 again", false).unwrap();
+
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = parent.tree().unwrap();
+        let second = repo.commit(Some("HEAD"), &signature, &signature, "second", &tree, &[&parent])
+            .unwrap();
+
+        // A tag of some other commit isn't taken as vouching for the checkout
+        let err = signed_object(&root, Some("v1"), &branch).unwrap_err().to_string();
+        assert!(err.contains(&second.to_string()), "{}", err);
+        let (object, _, is_tag) = signed_object(&root, None, &branch).unwrap();
+        assert_eq!((object, is_tag), (format!("commit {}", second), false));
+
+        // Tags of tags are peeled, and a `--branch` naming a tag is checked as one
+        repo.set_head_detached(first.id()).unwrap();
+        for &(rev, branch) in &[(Some("v1-again"), &branch[..]), (None, "v1-again")] {
+            let (object, _, is_tag) = signed_object(&root, rev, branch).unwrap();
+            assert_eq!((&object[..], is_tag), ("tag 'v1-again'", true));
+        }
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn loose_objects_packed() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+        use super::pack_objects;
+
+        let root = temp_dir("pack-objects");
+        origin_repo(&root, "contents");
+        let packs = || {
+            fs::read_dir(root.join(".git/objects/pack"))
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("pack".as_ref()))
+                .count()
+        };
+        assert_eq!(packs(), 0);
+
+        let (before, after) = pack_objects(&root).unwrap();
+        assert!(before > 0 && after > 0);
+        assert_eq!(packs(), 1);
+        assert_eq!(fs::read_to_string(root.join("tracked.txt")).unwrap(), "contents");
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn merge_keeps_untracked_files() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("merge-repo");
+        let (origin, target) = (root.join("origin"), root.join("target"));
+
+        let (branch, head) = origin_repo(&origin, "new");
+
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("tracked.txt"), "old").unwrap();
+        fs::write(target.join("extra.txt"), "mine").unwrap();
+
+        let mut report = GetReport::default();
+        merge_repo(
+            &target,
+            origin.to_str().unwrap(),
+            &branch,
+            &CloneOptions::default(),
+            &mut report,
+        ).unwrap();
+
+        assert_eq!(fs::read_to_string(target.join("tracked.txt")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(target.join("extra.txt")).unwrap(), "mine");
+        assert_eq!(report.commit, Some(head));
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn merge_keeps_files_removed_upstream() {
+        use std::fs;
+        use git2::{Repository, Signature, Status};
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("merge-removed");
+        let (origin, target) = (root.join("origin"), root.join("target"));
+        let (branch, _) = origin_repo(&origin, "old");
+
+        // A second commit that deletes a file the first one had
+        let repo = Repository::open(&origin).unwrap();
+        fs::create_dir_all(origin.join("docs")).unwrap();
+        fs::write(origin.join("docs/gone.txt"), "was tracked").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("docs/gone.txt")).unwrap();
+        let commit = |index: &mut ::git2::Index, message| {
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = repo.head().unwrap().peel_to_commit().unwrap();
+            let signature = Signature::now("test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &[&parent])
+                .unwrap()
+        };
+        commit(&mut index, "add gone.txt");
+
+        let merge = || {
+            let mut report = GetReport::default();
+            let url = origin.to_str().unwrap();
+            merge_repo(&target, url, &branch, &CloneOptions::default(), &mut report).unwrap();
+            report.commit.unwrap()
+        };
+        fs::create_dir_all(&target).unwrap();
+        merge();
+        fs::write(target.join("docs/gone.txt"), "edited").unwrap();
+
+        index.remove_path(Path::new("docs/gone.txt")).unwrap();
+        fs::write(origin.join("tracked.txt"), "new").unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        let head = commit(&mut index, "remove gone.txt");
+
+        assert_eq!(merge(), head.to_string());
+        assert_eq!(fs::read_to_string(target.join("tracked.txt")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(target.join("docs/gone.txt")).unwrap(), "edited");
+        let status = Repository::open(&target).unwrap().status_file(Path::new("docs/gone.txt"));
+        assert_eq!(status.unwrap(), Status::WT_NEW);
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn get_only_if_missing() {
+        use std::fs;
+        use fs2;
+        use util::{NameOrPath, OutputLayout};
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("only-if-missing");
+        let (origin, target) = (root.join("origin"), root.join("target"));
+        let (branch, head) = origin_repo(&origin, "contents");
+
+        let (origin, target_path) = (origin.to_str().unwrap(), target.to_str().unwrap());
+        let get = || {
+            let path = NameOrPath::try_from_path_or_name(Some(target_path), None).unwrap();
+            let layout = OutputLayout::Nested;
+            let mut get = Get::new_backend(path, &branch, false, origin, layout, &root).unwrap();
+            get.only_if_missing = true;
+            get
+        };
+
+        let report = get().get().unwrap();
+        assert!(!report.skipped);
+        assert_eq!(report.commit.as_ref(), Some(&head));
+
+        fs::write(target.join("tracked.txt"), "edited").unwrap();
+        let report = get().get().unwrap();
+        assert!(report.skipped);
+        assert_eq!(report.commit, Some(head.clone()));
+        assert_eq!(fs::read_to_string(target.join("tracked.txt")).unwrap(), "edited");
+
+        let mut pinned = get();
+        pinned.clone_options.rev = Some(&head[..8]);
+        assert!(pinned.get().unwrap().skipped);
+        pinned = get();
+        pinned.clone_options.rev = Some("0000000");
+        assert!(pinned.get().is_err());
+
+        fs2::remove_dir_all(target.join(".git")).unwrap();
+        assert!(get().get().is_err());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn force_at_ref_replaces_checkout() {
+        use std::fs;
+        use fs2;
+        use util::{NameOrPath, OutputLayout};
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("force-ref");
+        let (origin, target) = (root.join("origin"), root.join("git/target"));
+        let (branch, head) = origin_repo(&origin, "contents");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("stale.txt"), "old").unwrap();
+
+        let (origin, target_path) = (origin.to_str().unwrap(), target.to_str().unwrap());
+        let get = |depth_limit| {
+            let path = NameOrPath::try_from_path_or_name(Some(target_path), None).unwrap();
+            let layout = OutputLayout::Nested;
+            let mut get = Get::new_backend(path, &branch, true, origin, layout, &root).unwrap();
+            get.clone_options.rev = Some(&head[..7]);
+            get.depth_limit = depth_limit;
+            get
+        };
+
+        let report = get(2).get().unwrap();
+        assert_eq!(report.commit, Some(head.clone()));
+        assert!(!target.join("stale.txt").exists());
+
+        // The depth guard still applies to what `-f` deletes
+        assert!(get(3).get().is_err());
+        assert!(target.join("tracked.txt").exists());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn force_replaces_flat_install() {
+        use std::fs;
+        use fs2;
+        use util::{NameOrPath, OutputLayout};
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("force-flat");
+        let (origin, target) = (root.join("origin"), root.join("flat-backend"));
+        let (branch, head) = origin_repo(&origin, "contents");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("stale.txt"), "old").unwrap();
+
+        // A flat install is only one level down, which the default limit allows for it
+        let name = NameOrPath::Name("flat-backend");
+        let origin = origin.to_str().unwrap();
+        let get = Get::new_backend(name, &branch, true, origin, OutputLayout::Flat, &root);
+        let report = get.unwrap().get().unwrap();
+        assert_eq!(report.commit, Some(head));
+        assert!(target.join("tracked.txt").exists());
+        assert!(!target.join("stale.txt").exists());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn clone_without_checkout() {
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("no-checkout");
+        let (origin, target) = (root.join("origin"), root.join("target"));
+        let (branch, head) = origin_repo(&origin, "contents");
+
+        let options = CloneOptions {
+            no_checkout: true,
+            ..CloneOptions::default()
+        };
+        let mut report = GetReport::default();
+        clone_repo(&target, origin.to_str().unwrap(), &branch, &options, &mut report).unwrap();
+
+        assert!(target.join(".git").is_dir());
+        assert!(!target.join("tracked.txt").exists());
+        assert_eq!(report.commit, Some(head));
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn clone_at_revisions() {
+        use std::fs;
+        use git2::{Repository, Signature};
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("clone-ref");
+        let origin = root.join("origin");
+        let (branch, first) = origin_repo(&origin, "first");
+
+        // An annotated tag on the first commit, and a second commit only on `feature`
+        let repo = Repository::open(&origin).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let first_commit = repo.find_commit(first.parse().unwrap()).unwrap();
+        repo.tag("v1", first_commit.as_object(), &signature, "v1", false).unwrap();
+        repo.branch("feature", &first_commit, false).unwrap();
+        let tree = repo.find_tree(first_commit.tree_id()).unwrap();
+        let second = repo.commit(
+            Some("refs/heads/feature"),
+            &signature,
+            &signature,
+            "second",
+            &tree,
+            &[&first_commit],
+        ).unwrap()
+            .to_string();
+
+        let clone = |name: &str, rev| {
+            let options = CloneOptions {
+                rev: Some(rev),
+                ..CloneOptions::default()
+            };
+            let mut report = GetReport::default();
+            let url = origin.to_str().unwrap();
+            clone_repo(root.join(name), url, &branch, &options, &mut report).map(|_| report.commit)
+        };
+
+        assert_eq!(clone("sha", &first[..7]).unwrap(), Some(first.clone()));
+        assert_eq!(clone("tag", "v1").unwrap(), Some(first.clone()));
+        assert_eq!(clone("branch", "feature").unwrap(), Some(second.clone()));
+        assert_eq!(clone("parent", "feature~1").unwrap(), Some(first.clone()));
+        assert_eq!(fs::read_to_string(root.join("tag/tracked.txt")).unwrap(), "first");
+
+        let err = clone("unknown", "no-such-rev").unwrap_err();
+        assert!(err.to_string().contains("Unknown revision 'no-such-rev'"), "{}", err);
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn commit_log_between_merges() {
+        use git2::{Repository, Signature};
+        use fs2;
+        use util::fixture::temp_dir;
+        use super::commit_log;
+
+        let root = temp_dir("commit-log");
+        let (_, first) = origin_repo(&root, "first");
+
+        let repo = Repository::open(&root).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let mut parent = repo.find_commit(first.parse().unwrap()).unwrap();
+        for summary in &["second", "third\n\nWith a body"] {
+            let tree = parent.tree().unwrap();
+            let id = repo.commit(Some("HEAD"), &signature, &signature, summary, &tree, &[&parent]);
+            parent = repo.find_commit(id.unwrap()).unwrap();
+        }
+        let head = parent.id().to_string();
+
+        let (changes, count) = commit_log(&root, &first, &head, 20).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(changes, vec![format!("{} third", &head[..7]), changes[1].clone()]);
+        assert!(changes[1].ends_with(" second"), "{}", changes[1]);
+
+        assert_eq!(commit_log(&root, &first, &head, 1).unwrap(), (vec![changes[0].clone()], 2));
+        assert_eq!(commit_log(&root, &head, &head, 20).unwrap(), (vec![], 0));
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn clone_fetch_tags() {
+        use git2::{Repository, Signature};
+        use fs2;
+        use util::fixture::temp_dir;
+        use super::FetchTags;
+
+        let root = temp_dir("clone-tags");
+        let origin = root.join("origin");
+        let (branch, head) = origin_repo(&origin, "first");
+
+        // One tag on the branch, and one on a commit no branch has
+        let repo = Repository::open(&origin).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let head = repo.find_commit(head.parse().unwrap()).unwrap();
+        repo.tag_lightweight("on-branch", head.as_object(), false).unwrap();
+        let tree = repo.find_tree(head.tree_id()).unwrap();
+        let orphan = repo.commit(None, &signature, &signature, "orphan", &tree, &[]).unwrap();
+        let orphan = repo.find_commit(orphan).unwrap();
+        repo.tag_lightweight("orphaned", orphan.as_object(), false).unwrap();
+
+        let tags = |name: &str, fetch_tags| {
+            let options = CloneOptions {
+                fetch_tags,
+                ..CloneOptions::default()
+            };
+            let mut report = GetReport::default();
+            let target = root.join(name);
+            clone_repo(&target, origin.to_str().unwrap(), &branch, &options, &mut report).unwrap();
+
+            let tags = Repository::open(&target).unwrap().tag_names(None).unwrap();
+            tags.iter().map(|tag| tag.unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // libgit2 copies every object of a local remote, so the orphaned tag's commit comes
+        // along too and only a network remote would leave it out
+        assert!(tags("auto", FetchTags::Auto).contains(&"on-branch".to_string()));
+        assert_eq!(tags("all", FetchTags::All), vec!["on-branch", "orphaned"]);
+        assert!(tags("none", FetchTags::None).is_empty());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn clone_with_reference() {
+        use std::fs;
+        use git2::Repository;
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("clone-reference");
+        let origin = root.join("origin");
+        let (branch, first) = origin_repo(&origin, "first");
+        let url = format!("file://{}", origin.display());
+
+        let mut report = GetReport::default();
+        let options = CloneOptions::default();
+        clone_repo(root.join("reference"), &url, &branch, &options, &mut report).unwrap();
+
+        let reference = root.join("reference");
+        let options = CloneOptions {
+            reference: Some(&reference),
+            ..CloneOptions::default()
+        };
+        let mut report = GetReport::default();
+        clone_repo(root.join("clone"), &url, &branch, &options, &mut report).unwrap();
+
+        assert_eq!(report.commit, Some(first));
+        assert_eq!(fs::read_to_string(root.join("clone/tracked.txt")).unwrap(), "first");
+        let alternates = fs::read_to_string(root.join("clone/.git/objects/info/alternates"));
+        assert!(alternates.unwrap().contains("reference"));
+
+        let repo = Repository::open(root.join("clone")).unwrap();
+        assert_eq!(repo.references_glob(BORROWED_REFS).unwrap().count(), 0);
+        let packs = fs::read_dir(root.join("clone/.git/objects/pack")).unwrap();
+        assert_eq!(packs.count(), 0, "objects were fetched despite the reference");
+
+        assert!(reference_objects(&root.join("clone/tracked.txt")).is_err());
+        assert!(reference_objects(&root).is_err());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn core_resources_detection() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+        use constants::{CLOSURE_LIB_DIR, DEFAULT_BRANCH, PROTOBUF_JS_DIR};
+
+        let root = temp_dir("core-resources-present");
+        let get = Get::new_core(root.to_str(), DEFAULT_BRANCH, false, &root);
+        let viz = root.join("viz/js");
+
+        fs::create_dir_all(viz.join(CLOSURE_LIB_DIR)).unwrap();
+        assert!(!get.core_resources_present());
+
+        fs::create_dir_all(viz.join(PROTOBUF_JS_DIR)).unwrap();
+        assert!(get.core_resources_present());
+
+        // A checkout that moved the visualization leaves the old dependencies behind
+        let moved = root.join("viz/src");
+        fs::create_dir_all(&moved).unwrap();
+        fs::rename(&viz, moved.join("js")).unwrap();
+        assert!(!get.core_resources_present());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn core_resources_under_checkout_prefix() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+        use constants::{CLOSURE_LIB_DIR, DEFAULT_BRANCH, DEP_EXTRACT_DIR, PROTOBUF_JS_DIR};
+        use std::path::Path;
+        use super::core_resource_dirs;
+        use util::parse_relative_dir;
+
+        assert_eq!(parse_relative_dir("prefix", "src").unwrap(), Path::new("src"));
+        assert_eq!(parse_relative_dir("prefix", "").unwrap(), Path::new(""));
+        assert!(parse_relative_dir("prefix", "src/checkout").is_ok());
+        assert!(parse_relative_dir("prefix", "../src").is_err());
+        assert!(parse_relative_dir("prefix", "src/../..").is_err());
+        assert!(parse_relative_dir("prefix", "/src").is_err());
+
+        let root = temp_dir("core-resources-prefix");
+        let mut get = Get::new_core(root.to_str(), DEFAULT_BRANCH, false, &root);
+        get.path.push(parse_relative_dir("prefix", "src").unwrap());
+
+        // Dependencies left in the install root belong to a checkout without the prefix
+        fs::create_dir_all(root.join("viz/js").join(CLOSURE_LIB_DIR)).unwrap();
+        fs::create_dir_all(root.join("viz/js").join(PROTOBUF_JS_DIR)).unwrap();
+        assert!(!get.core_resources_present());
+
+        let viz = root.join("src/viz/js");
+        for dir in &core_resource_dirs(get.path(), Path::new(DEP_EXTRACT_DIR)) {
+            assert!(dir.starts_with(&viz), "{}", dir.display());
+            fs::create_dir_all(dir).unwrap();
+        }
+        assert!(get.core_resources_present());
+        assert!(get.describe_core_resources().contains(&*viz.display().to_string()));
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn deps_fetched_together_or_in_turn() {
+        use std::fs;
+        use fs2;
+        use util::fixture::{temp_dir, zip_fixture, Fixture, FixtureServer};
+        use constants::{CLOSURE_LIB_DIR, DEFAULT_BRANCH, PROTOBUF_JS_DIR};
+
+        // Requests may come in either order, so both are answered with an archive that
+        // unpacks as either dependency
+        let archive = zip_fixture(&[
+            ("protobuf-3.5.1/", b""),
+            ("protobuf-3.5.1/js/", b""),
+            ("protobuf-3.5.1/js/message.js", b"message"),
+        ]);
+        let server = FixtureServer::start(vec![Fixture::ok(archive)]);
+        let mirror = server.url("");
+
+        for &concurrent in &[true, false] {
+            let root = temp_dir(&format!("deps-concurrently-{}", concurrent));
+            let viz = root.join("viz/js");
+            fs::create_dir_all(&viz).unwrap();
+
+            let mut get = Get::new_core(root.to_str(), DEFAULT_BRANCH, false, &root);
+            get.dep_mirror = Some(&mirror);
+            get.concurrent_deps = concurrent;
+            let mut report = GetReport::default();
+            get.get_core_resources(&mut report).unwrap();
+
+            let dirs: Vec<_> = report.dependency_urls.iter().map(|&(dir, _)| dir).collect();
+            assert_eq!(dirs, [CLOSURE_LIB_DIR, PROTOBUF_JS_DIR]);
+            assert!(viz.join(CLOSURE_LIB_DIR).join("js/message.js").is_file());
+            assert_eq!(fs::read(viz.join(PROTOBUF_JS_DIR).join("message.js")).unwrap(), b"message");
+            assert!(!viz.join("protobuf-3.5.1").exists());
+
+            fs2::remove_dir_all(&root).unwrap();
+        }
+        assert_eq!(server.hits(), 4);
+    }
+
+    #[test]
+    fn existing_deps_skipped() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+        use util::RetryPolicy;
+        use constants::{CLOSURE_LIB_DIR, DEFAULT_BRANCH, PROTOBUF_JS_DIR};
+
+        let root = temp_dir("skip-existing-deps");
+        let viz = root.join("viz/js");
+        fs::create_dir_all(viz.join(CLOSURE_LIB_DIR)).unwrap();
+        fs::write(viz.join(CLOSURE_LIB_DIR).join("base.js"), "").unwrap();
+        fs::create_dir_all(viz.join(PROTOBUF_JS_DIR)).unwrap();
+
+        // Nothing listens there, so any download fails
+        let mut get = Get::new_core(root.to_str(), DEFAULT_BRANCH, false, &root);
+        get.dep_mirror = Some("http://127.0.0.1:9");
+        get.curl_options.connect = RetryPolicy::parse("attempts=1").unwrap();
+        get.skip_existing_deps = true;
+
+        // An empty directory isn't trusted
+        let mut report = GetReport::default();
+        assert!(get.get_core_resources(&mut report).is_err());
+
+        fs::write(viz.join(PROTOBUF_JS_DIR).join("map.js"), "").unwrap();
+        get.get_core_resources(&mut report).unwrap();
+        assert_eq!(report.downloaded_bytes, 0);
+        assert!(report.dependency_urls.is_empty());
+
+        get.skip_existing_deps = false;
+        assert!(get.get_core_resources(&mut report).is_err());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn core_resources_in_dep_dir() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+        use constants::{CLOSURE_LIB_DIR, DEFAULT_BRANCH, PROTOBUF_JS_DIR};
+        use std::path::Path;
+        use super::core_resource_dirs;
+        use util::parse_relative_dir;
+
+        assert!(parse_relative_dir("dir", "web/../../js").is_err());
+
+        let root = temp_dir("core-resources-dep-dir");
+        let mut get = Get::new_core(root.to_str(), DEFAULT_BRANCH, false, &root);
+        get.dep_dir = parse_relative_dir("dir", "./web/static").unwrap();
+        assert_eq!(get.dep_dir, Path::new("web/static"));
+
+        fs::create_dir_all(root.join("viz/js").join(CLOSURE_LIB_DIR)).unwrap();
+        fs::create_dir_all(root.join("viz/js").join(PROTOBUF_JS_DIR)).unwrap();
+        assert!(!get.core_resources_present());
+
+        let viz = root.join("web/static");
+        for dir in &core_resource_dirs(get.path(), &get.dep_dir) {
+            assert!(dir.starts_with(&viz), "{}", dir.display());
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("kept.js"), "").unwrap();
+        }
+        assert!(get.core_resources_present());
+        assert!(get.describe_core_resources().contains(&*viz.display().to_string()));
+
+        // Pushing the dependency directory and popping it back leaves the checkout path
+        get.skip_existing_deps = true;
+        get.get_core_resources(&mut GetReport::default()).unwrap();
+        assert_eq!(get.path(), root.as_path());
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn validation() {
+        use std::path::Path;
+        use error::ErrorKind;
+        use util::{NameOrPath, OutputLayout};
+
+        let scaii_dir = Path::new("scaii");
+        let backend = |url, branch| {
+            let name = NameOrPath::Name("my-backend");
+            let layout = OutputLayout::Nested;
+            Get::new_backend(name, branch, false, url, layout, scaii_dir).unwrap()
+        };
+        let valid = |url, branch| backend(url, branch).validate().map(|_| ());
+
+        assert!(valid("https://github.com/team/my-backend.git", "master").is_ok());
+        assert!(valid("git@github.com:team/my-backend.git", "feature/x").is_ok());
+        assert!(valid("/srv/git/my backend", "v1.0").is_ok());
+        assert!(Get::new_core(None, "master", false, scaii_dir).validate().is_ok());
+
+        for url in &["", "  ", " /srv/git/repo", "https://", "://host/repo", "ht tp://host/repo",
+                     "https://host/my backend"]
+        {
+            match *valid(url, "master").unwrap_err().kind() {
+                ErrorKind::InvalidUrl(ref invalid) if invalid == url => {}
+                ref kind => panic!("unexpected error for '{}': {}", url, kind),
+            }
+        }
+        for branch in &["", "my branch", "master\n"] {
+            match *valid("https://host/repo", branch).unwrap_err().kind() {
+                ErrorKind::InvalidRef(ref invalid) if invalid == branch => {}
+                ref kind => panic!("unexpected error for '{}': {}", branch, kind),
+            }
+        }
+
+        let mut get = backend("https://host/repo", "master");
+        get.clone_options.rev = Some("");
+        assert!(get.validate().is_err());
+        get.clone_options.rev = Some("v1");
+        assert!(get.validate().is_ok());
+    }
+
+    #[test]
+    fn inferred_name_is_validated() {
+        use std::path::Path;
+        use util::{NameOrPath, OutputLayout};
+
+        let scaii_dir = Path::new("scaii");
+        let backend = |url| {
+            let name = NameOrPath::name_from_url(url).unwrap();
+            let layout = OutputLayout::Nested;
+            Get::new_backend(NameOrPath::Name(name), "master", false, url, layout, scaii_dir)
+        };
+
+        assert_eq!(
+            backend("https://github.com/team/my-backend.git").unwrap().path,
+            scaii_dir.join("git").join("my-backend")
+        );
+        assert!(backend("https://github.com/SCAII/SCAII.git").is_err());
+        assert!(backend("https://github.com/SCAII/Sky-RTS/").is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn local_changes_detection() {
+        use std::fs;
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let repo = temp_dir("local-changes");
+        assert!(!has_local_changes(&repo).unwrap());
+
+        origin_repo(&repo, "committed");
+        assert!(!has_local_changes(&repo).unwrap());
+
+        fs::write(repo.join("untracked.txt"), "new").unwrap();
+        assert!(!has_local_changes(&repo).unwrap());
+
+        fs::write(repo.join("tracked.txt"), "edited").unwrap();
+        assert!(has_local_changes(&repo).unwrap());
+
+        fs2::remove_dir_all(&repo).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn clone_from_bare_mirror() {
+        use std::fs;
+        use git2::Repository;
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("bare-mirror");
+        let (origin, mirror, target) = (
+            root.join("origin"),
+            root.join("mirror/SCAII.git"),
+            root.join("target"),
+        );
+        let (branch, head) = origin_repo(&origin, "contents");
+
+        assert!(!is_bare_repo(&mirror));
+        assert!(!is_bare_repo(&origin));
+
+        Repository::init_bare(&mirror)
+            .unwrap()
+            .remote_anonymous(origin.to_str().unwrap())
+            .unwrap()
+            .fetch(&["+refs/heads/*:refs/heads/*"], None, None)
+            .unwrap();
+        assert!(is_bare_repo(&mirror));
+
+        let mut report = GetReport::default();
+        let options = CloneOptions::default();
+        clone_repo(&target, mirror.to_str().unwrap(), &branch, &options, &mut report).unwrap();
+
+        assert_eq!(fs::read_to_string(target.join("tracked.txt")).unwrap(), "contents");
+        assert_eq!(report.commit, Some(head));
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn github_tarball_urls() {
+        use super::github_tarball_url;
+
+        let tarball = Some("https://codeload.github.com/team/my-backend/tar.gz/master".to_string());
+        assert_eq!(github_tarball_url("https://github.com/team/my-backend.git", "master"), tarball);
+        assert_eq!(github_tarball_url("git@github.com:team/my-backend.git", "master"), tarball);
+        assert_eq!(github_tarball_url("ssh://git@GitHub.com/team/my-backend", "master"), tarball);
+        assert_eq!(
+            github_tarball_url("https://github.com/team/my-backend", "v1.0"),
+            Some("https://codeload.github.com/team/my-backend/tar.gz/v1.0".to_string())
+        );
+
+        assert_eq!(github_tarball_url("https://gitlab.com/team/my-backend.git", "master"), None);
+        assert_eq!(github_tarball_url("https://github.com.evil/team/repo", "master"), None);
+        assert_eq!(github_tarball_url("https://github.com/my-backend", "master"), None);
+        assert_eq!(github_tarball_url("/srv/git/github.com/team/repo", "master"), None);
+    }
+
+    #[test]
+    fn tarball_fetch() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::fs;
+        use std::io::Write;
+        use fs2;
+        use util::fixture::{temp_dir, Fixture, FixtureServer};
+        use util::tar::TarWriter;
+        use util::CurlOptions;
+        use super::fetch_tarball;
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_dir("my-backend-master", 0o755).unwrap();
+        tar.append_dir("my-backend-master/src", 0o755).unwrap();
+        tar.append_file("my-backend-master/src/main.rs", 0o644, b"fn main() {}").unwrap();
+        // `git archive` puts paths over 100 bytes in pax headers
+        let long = format!("src/{}.rs", "long_module_name".repeat(7));
+        tar.append_pax_file(&format!("my-backend-master/{}", long), 0o644, b"").unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar.finish().unwrap()).unwrap();
+
+        let server = FixtureServer::start(vec![Fixture::ok(gz.finish().unwrap())]);
+        let target = temp_dir("tarball-fetch");
+        let mut report = GetReport {
+            resource: "backend",
+            ..GetReport::default()
+        };
+        let url = server.url("/team/my-backend/tar.gz/master");
+        fetch_tarball(&target, &url, &CurlOptions::default(), None, &mut report).unwrap();
+
+        assert_eq!(fs::read_to_string(target.join("src/main.rs")).unwrap(), "fn main() {}");
+        assert!(target.join(&long).is_file());
+        assert!(!target.join(".git").exists() && !target.join("my-backend-master").exists());
+        assert!(report.tarball);
+        // Only GitHub says which commit it was
+        assert_eq!(report.commit, None);
+        assert!(report.to_env().contains("SCAII_BACKEND_TARBALL=1\n"));
+
+        fs2::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn tarball_escapes() {
+        use fs2;
+        use util::fixture::temp_dir;
+        use util::tar::{TarReader, TarWriter};
+        use super::extract_tarball;
+
+        fn extract<F: Fn(&mut TarWriter<Vec<u8>>)>(build: F) -> ::error::Result<()> {
+            let mut tar = TarWriter::new(Vec::new());
+            tar.append_dir("repo-master", 0o755).unwrap();
+            build(&mut tar);
+            let tar = tar.finish().unwrap();
+
+            let target = temp_dir("tarball-escapes");
+            let result = extract_tarball(&mut TarReader::new(&tar[..]), &target, None);
+            fs2::remove_dir_all(&target).unwrap();
+            result
+        }
+
+        let file = |path: &'static str| move |tar: &mut TarWriter<Vec<u8>>| {
+            tar.append_file(path, 0o644, b"a").unwrap()
+        };
+        assert!(extract(file("repo-master/a.txt")).is_ok());
+        assert!(extract(file("repo-master/../a.txt")).is_err());
+        assert!(extract(file("a.txt")).is_err());
+
+        #[cfg(unix)]
+        assert!(extract(|tar: &mut TarWriter<Vec<u8>>| {
+            tar.append_symlink("repo-master/link", "/tmp").unwrap();
+        }).is_err());
+        #[cfg(unix)]
+        assert!(extract(|tar: &mut TarWriter<Vec<u8>>| {
+            tar.append_symlink("repo-master/link", "..").unwrap();
+            tar.append_file("repo-master/link/a.txt", 0o644, b"a").unwrap();
+        }).is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn newest_commit_at_time() {
+        use git2::{Repository, Signature, Time};
+        use fs2;
+        use util::fixture::temp_dir;
+
+        let dir = temp_dir("commit-at");
+        let repo = Repository::init(&dir).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+
+        let mut commits = Vec::new();
+        for &time in &[100, 300, 200] {
+            let signature = Signature::new("test", "test@example.com", &Time::new(time, 0)).unwrap();
+            let parent = commits.last().map(|&id| repo.find_commit(id).unwrap());
+            let parents: Vec<_> = parent.iter().collect();
+            let commit = repo.commit(Some("HEAD"), &signature, &signature, "commit", &tree, &parents);
+            commits.push(commit.unwrap());
+        }
+
+        assert_eq!(commit_at(&repo, 250).unwrap(), Some(commits[2]));
+        assert_eq!(commit_at(&repo, 1_000).unwrap(), Some(commits[1]));
+        assert_eq!(commit_at(&repo, 100).unwrap(), Some(commits[0]));
+        assert_eq!(commit_at(&repo, 50).unwrap(), None);
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn newest_commit_with_message() {
+        use git2::{Repository, Signature, Time};
+        use fs2;
+        use util::fixture::temp_dir;
+        use util::regex::Regex;
+        use super::{commit_with_message, message_matches};
+
+        let dir = temp_dir("commit-with-message");
+        let repo = Repository::init(&dir).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+
+        let messages = ["Release v1.0", "Fix the build", "Bump\n\n  Release v1.1\n", "Refactor"];
+        let mut commits = Vec::new();
+        for (time, message) in messages.iter().enumerate() {
+            let time = Time::new(100 * (time as i64 + 1), 0);
+            let signature = Signature::new("test", "test@example.com", &time).unwrap();
+            let parent = commits.last().map(|&id| repo.find_commit(id).unwrap());
+            let parents: Vec<_> = parent.iter().collect();
+            let commit = repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+                .unwrap();
+            commits.push(commit);
+        }
+
+        let pattern = |pattern| Regex::new(pattern).unwrap();
+        assert!(message_matches(&pattern("^Release v1"), "Bump\n\n  Release v1.1\n"));
+        assert!(message_matches(&pattern("v1\\.0$"), "Release v1.0"));
+        assert!(!message_matches(&pattern("^Bump$"), "Bump to v1.1"));
+        assert!(!message_matches(&pattern("Bump.*Release"), "Bump\n\n  Release v1.1\n"));
+
+        let find = |found| commit_with_message(&repo, &pattern(found)).unwrap();
+        assert_eq!(find("^Release v1\\.\\d+$"), Some(commits[2]));
+        assert_eq!(find("Release v1\\.0"), Some(commits[0]));
+        assert_eq!(find("build"), Some(commits[1]));
+        assert_eq!(find("Release v2"), None);
+
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+}
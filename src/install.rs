@@ -0,0 +1,705 @@
+use clap::ArgMatches;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use error;
+
+use bundle::{self, Manifest};
+use get::Get;
+use uninstall::is_file_name;
+use util::{self, NameOrPath, OutputLayout};
+use constants::*;
+
+/// The build systems `install` knows how to drive, by the file that gives each away.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum BuildSystem {
+    /// `Cargo.toml`
+    Cargo,
+    /// `package.json`
+    Npm,
+    /// `package.json` with a `yarn.lock` next to it
+    Yarn,
+    /// `Makefile`, `makefile` or `GNUmakefile`
+    Make,
+    /// `CMakeLists.txt`
+    CMake,
+}
+
+impl BuildSystem {
+    /// The build system of the project in `dir`. A project with several, such as a crate
+    /// with a convenience `Makefile`, gets the first in the order of `BuildSystem`.
+    pub fn detect(dir: &Path) -> Option<Self> {
+        let has = |file: &str| dir.join(file).is_file();
+
+        if has("Cargo.toml") {
+            Some(BuildSystem::Cargo)
+        } else if has("package.json") && has("yarn.lock") {
+            Some(BuildSystem::Yarn)
+        } else if has("package.json") {
+            Some(BuildSystem::Npm)
+        } else if ["Makefile", "makefile", "GNUmakefile"].iter().any(|file| has(file)) {
+            Some(BuildSystem::Make)
+        } else if has("CMakeLists.txt") {
+            Some(BuildSystem::CMake)
+        } else {
+            None
+        }
+    }
+
+    /// The binaries a build of the project in `dir` leaves behind. npm, yarn and make
+    /// builds don't put them anywhere in particular.
+    pub fn artifacts(self, dir: &Path) -> Vec<PathBuf> {
+        match self {
+            BuildSystem::Cargo => executables(&dir.join("target").join("release")),
+            BuildSystem::CMake => executables(&dir.join("build")),
+            BuildSystem::Npm | BuildSystem::Yarn | BuildSystem::Make => Vec::new(),
+        }
+    }
+
+    /// The commands that build the project, to be run in order from its directory.
+    pub fn commands(self) -> &'static [&'static [&'static str]] {
+        match self {
+            BuildSystem::Cargo => &[&["cargo", "build", "--release"]],
+            BuildSystem::Npm => &[&["npm", "install"]],
+            BuildSystem::Yarn => &[&["yarn", "install"]],
+            BuildSystem::Make => &[&["make"]],
+            BuildSystem::CMake => &[
+                &["cmake", "-S", ".", "-B", "build"],
+                &["cmake", "--build", "build"],
+            ],
+        }
+    }
+}
+
+/// Builds a resource where it's checked out, fetching it first for `install backend
+/// --remote`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Install<'a> {
+    path: PathBuf,
+    /// Run through the shell instead of the detected build
+    build_command: Option<&'a str>,
+    /// Set for the build on top of the inherited environment
+    envs: Vec<(&'a str, &'a str)>,
+    /// Fetches the resource to `path` before building it
+    fetch: Option<Get<'a>>,
+    /// Where the built binaries go
+    prefix: PathBuf,
+    /// Copy the binaries into `prefix` instead of symlinking them
+    copy: bool,
+    /// The binaries to put in `prefix` instead of the detected ones, relative to `path`
+    artifacts: Vec<&'a str>,
+    /// What the record of the installed binaries is named after, the directory's name
+    /// if not known
+    name: Option<&'a str>,
+    scaii_dir: PathBuf,
+}
+
+/// Unpacks a resource from a file made by `get --bundle` instead of building it, for
+/// `install --from-bundle`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct FromBundle<'a> {
+    pub file: &'a Path,
+    /// Where to unpack to instead of `~/.scaii/git/<NAME>`
+    target: Option<&'a Path>,
+    /// The resource given on the command line, which the bundle has to be of
+    resource: Option<&'a str>,
+    /// Replace an existing target instead of erroring on it
+    force: bool,
+    /// How far below `scaii_dir` a directory must be for `force` to delete it
+    depth_limit: usize,
+    scaii_dir: PathBuf,
+}
+
+/// What `install` put into a prefix for a resource, kept under `~/.scaii/installed`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Installed {
+    pub prefix: PathBuf,
+    /// The file names of the binaries in `prefix`
+    pub artifacts: Vec<String>,
+}
+
+/// The first line of every record, the number is bumped whenever the format changes.
+const RECORD_HEADER: &str = "better-install installed 1";
+
+impl Installed {
+    /// Where the record for the resource `name` is kept.
+    pub fn path(scaii_dir: &Path, name: &str) -> PathBuf {
+        scaii_dir.join(INSTALLED_DIR).join(name)
+    }
+
+    pub fn parse(record: &str) -> error::Result<Self> {
+        let mut lines = record.lines();
+        ensure!(
+            lines.next() == Some(RECORD_HEADER),
+            "The install record doesn't start with '{}'",
+            RECORD_HEADER
+        );
+
+        let mut prefix = None;
+        let mut artifacts = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("prefix"), Some(path)) => prefix = Some(PathBuf::from(path)),
+                (Some("artifact"), Some(name)) => artifacts.push(name.to_string()),
+                _ => bail!("Unexpected line in the install record: '{}'", line),
+            }
+        }
+
+        match prefix {
+            Some(prefix) => Ok(Installed { prefix, artifacts }),
+            None => bail!("The install record doesn't say which prefix it's for"),
+        }
+    }
+
+    pub fn to_record(&self) -> String {
+        let mut record = format!("{}\nprefix {}\n", RECORD_HEADER, self.prefix.display());
+        for artifact in &self.artifacts {
+            record.push_str(&format!("artifact {}\n", artifact));
+        }
+
+        record
+    }
+}
+
+impl<'a> Install<'a> {
+    pub fn from_subcommand(
+        subcommand: &'a ArgMatches<'a>,
+        scaii_dir: &Path,
+    ) -> error::Result<Self> {
+        let (resource, args) = match subcommand.subcommand() {
+            (resource, Some(args)) => (resource, args),
+            _ => usage_and_exit!(subcommand),
+        };
+
+        let path = global(subcommand, "path").map(PathBuf::from);
+        let build_command = global(subcommand, "build-command");
+        let envs = match args.values_of("env").or(subcommand.values_of("env")) {
+            Some(envs) => envs.map(parse_env).collect::<error::Result<_>>()?,
+            None => Vec::new(),
+        };
+
+        let prefix = match global(subcommand, "prefix") {
+            Some(prefix) => PathBuf::from(prefix),
+            None => scaii_dir.join(BIN_DIR),
+        };
+        let copy = global_flag(subcommand, "copy");
+        let artifacts = match args.values_of("artifact").or(subcommand.values_of("artifact")) {
+            Some(artifacts) => artifacts.collect(),
+            None => Vec::new(),
+        };
+
+        let known = |name| match path {
+            Some(ref path) => path.clone(),
+            // A checkout of the resource being worked on is used in place
+            None if BuildSystem::detect(Path::new(".")).is_some() => PathBuf::from("."),
+            None => NameOrPath::Name(name).to_path_buf(scaii_dir),
+        };
+
+        let (path, fetch, name) = match resource {
+            "core" => (known(CORE_NAME), None, Some(CORE_NAME)),
+            "rts" => (known(RTS_NAME), None, Some(RTS_NAME)),
+            "backend" => match (path, args.value_of("remote")) {
+                (Some(path), _) => (path, None, args.value_of("name")),
+                (None, Some(url)) => {
+                    let save_path = global(subcommand, "save-path");
+                    let name = match (save_path, args.value_of("name")) {
+                        (None, None) => match NameOrPath::name_from_url(url) {
+                            Some(name) => Some(name),
+                            None => bail!(
+                                "Cannot infer a backend name from '{}' (Hint: give one with \
+                                 '--name')",
+                                url
+                            ),
+                        },
+                        (_, name) => name,
+                    };
+
+                    let get = Get::new_backend(
+                        NameOrPath::try_from_path_or_name(save_path, name).unwrap(),
+                        global(subcommand, "branch").unwrap_or(DEFAULT_BRANCH),
+                        false,
+                        url,
+                        OutputLayout::Nested,
+                        scaii_dir,
+                    )?;
+                    get.validate()?;
+                    (get.path().to_path_buf(), Some(get), name)
+                }
+                (None, None) => match args.value_of("name") {
+                    Some(name) => (NameOrPath::Name(name).to_path_buf(scaii_dir), None, Some(name)),
+                    None => usage_and_exit!(subcommand),
+                },
+            },
+            _ => usage_and_exit!(subcommand),
+        };
+        // The install is recorded under its name, which mustn't lead anywhere else
+        if let Some(name) = name {
+            ensure!(
+                is_file_name(name),
+                "Invalid resource name '{}' (Note: expected the name of its directory, e.g. \
+                 'my-backend')",
+                name
+            );
+        }
+
+        Ok(Install {
+            path,
+            build_command,
+            envs,
+            fetch,
+            prefix,
+            copy,
+            artifacts,
+            name,
+            scaii_dir: scaii_dir.to_path_buf(),
+        })
+    }
+
+    pub fn install(self) -> error::Result<PathBuf> {
+        use error::ResultExt;
+
+        if let Some(get) = self.fetch {
+            let report = get.get().chain_err(|| "Could not fetch the backend to install")?;
+            for warning in &report.warnings {
+                log_eprintln!("Warning: {}", warning);
+            }
+        }
+
+        ensure!(
+            self.path.is_dir(),
+            "Nothing to install at '{}' (Hint: fetch it with `get` first, or point at a \
+             checkout with --path)",
+            self.path.display()
+        );
+
+        let system = BuildSystem::detect(&self.path);
+        match (self.build_command, system) {
+            (Some(command), _) => run_build(&self.path, shell(command), command, &self.envs)?,
+            (None, Some(system)) => {
+                for args in system.commands() {
+                    run_build(&self.path, program(args), &args.join(" "), &self.envs)?;
+                }
+            }
+            (None, None) => bail!(
+                "No Cargo.toml, package.json, Makefile or CMakeLists.txt in '{}' (Hint: say \
+                 how to build it with --build-command)",
+                self.path.display()
+            ),
+        }
+
+        let artifacts = if self.artifacts.is_empty() {
+            match system {
+                Some(system) => system.artifacts(&self.path),
+                None => Vec::new(),
+            }
+        } else {
+            let mut artifacts = Vec::with_capacity(self.artifacts.len());
+            for artifact in &self.artifacts {
+                let artifact = self.path.join(artifact);
+                ensure!(
+                    artifact.is_file(),
+                    "The build left no '{}' to install",
+                    artifact.display()
+                );
+                artifacts.push(artifact);
+            }
+            artifacts
+        };
+
+        if !artifacts.is_empty() {
+            let installed = place_artifacts(&artifacts, &self.prefix, self.copy)?;
+            let name = match self.name {
+                Some(name) => name.to_string(),
+                None => match fs::canonicalize(&self.path)?.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => bail!(
+                        "Cannot tell what to record the install of '{}' as (Hint: give a \
+                         '--name')",
+                        self.path.display()
+                    ),
+                },
+            };
+
+            let record = Installed::path(&self.scaii_dir, &name);
+            fs::create_dir_all(record.parent().unwrap())?;
+            fs::write(&record, installed.to_record())?;
+        }
+
+        Ok(self.path)
+    }
+}
+
+impl<'a> FromBundle<'a> {
+    /// The bundle to install from, if `--from-bundle` was given.
+    pub fn from_subcommand(
+        subcommand: &'a ArgMatches<'a>,
+        scaii_dir: &Path,
+    ) -> error::Result<Option<Self>> {
+        let file = match global(subcommand, "from-bundle") {
+            Some(file) => Path::new(file),
+            None => return Ok(None),
+        };
+        let resource = match subcommand.subcommand_name() {
+            Some(resource) if !resource.is_empty() => Some(resource),
+            _ => None,
+        };
+        let depth_limit = match global(subcommand, "depth-limit") {
+            Some(limit) => util::parse_depth_limit(limit)?,
+            None => DEFAULT_DEPTH_LIMIT,
+        };
+
+        Ok(Some(FromBundle {
+            file,
+            target: global(subcommand, "save-path").map(Path::new),
+            resource,
+            force: global_flag(subcommand, "force"),
+            depth_limit,
+            scaii_dir: scaii_dir.to_path_buf(),
+        }))
+    }
+
+    /// Unpacks the bundle, returning its manifest and where it was unpacked to.
+    pub fn unpack(self) -> error::Result<(Manifest, PathBuf)> {
+        bundle::unpack(
+            self.file,
+            self.target,
+            self.resource,
+            self.force,
+            self.depth_limit,
+            &self.scaii_dir,
+        )
+    }
+}
+
+/// A global argument of `install`, which lands in the matches of the resource instead
+/// when it's given after it.
+fn global<'a>(subcommand: &'a ArgMatches<'a>, name: &str) -> Option<&'a str> {
+    let args = subcommand.subcommand().1;
+    args.and_then(|args| args.value_of(name)).or(subcommand.value_of(name))
+}
+
+/// Whether a global flag of `install` was given, before or after the resource.
+fn global_flag(subcommand: &ArgMatches, name: &str) -> bool {
+    let args = subcommand.subcommand().1;
+    args.into_iter().any(|args| args.is_present(name)) || subcommand.is_present(name)
+}
+
+/// Puts `artifacts` into `prefix`, replacing whatever has the same name there.
+fn place_artifacts(artifacts: &[PathBuf], prefix: &Path, copy: bool) -> error::Result<Installed> {
+    use error::ResultExt;
+
+    fs::create_dir_all(prefix)
+        .chain_err(|| format!("Could not create the prefix '{}'", prefix.display()))?;
+    let prefix = fs::canonicalize(prefix)?;
+
+    let mut installed = Vec::with_capacity(artifacts.len());
+    for artifact in artifacts {
+        let name = artifact.file_name().unwrap();
+        let target = prefix.join(name);
+        if target.symlink_metadata().is_ok() {
+            fs::remove_file(&target)
+                .chain_err(|| format!("Could not replace '{}'", target.display()))?;
+        }
+
+        let artifact = fs::canonicalize(artifact)?;
+        place(&artifact, &target, copy)
+            .chain_err(|| format!("Could not install '{}'", artifact.display()))?;
+        log_progress!("Installed '{}' as '{}'", artifact.display(), target.display());
+
+        installed.push(name.to_string_lossy().into_owned());
+    }
+
+    Ok(Installed {
+        prefix,
+        artifacts: installed,
+    })
+}
+
+#[cfg(unix)]
+fn place(artifact: &Path, target: &Path, copy: bool) -> ::std::io::Result<()> {
+    if copy {
+        fs::copy(artifact, target).map(|_| ())
+    } else {
+        ::std::os::unix::fs::symlink(artifact, target)
+    }
+}
+
+/// Symlinks need extra privileges on Windows, so binaries are always copied there.
+#[cfg(not(unix))]
+fn place(artifact: &Path, target: &Path, _copy: bool) -> ::std::io::Result<()> {
+    fs::copy(artifact, target).map(|_| ())
+}
+
+/// The executables directly in `dir`, leaving out the libraries and dependency files a
+/// cargo build puts next to them.
+fn executables(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut executables: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_executable(path))
+        .collect();
+    executables.sort();
+
+    executables
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let library = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => ["so", "dylib", "d", "rlib", "a"].contains(&extension),
+        None => false,
+    };
+
+    !library && fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.eq_ignore_ascii_case("exe"))
+        .unwrap_or(false)
+}
+
+/// Parses an `--env`, which the build gets as an environment variable.
+fn parse_env(env: &str) -> error::Result<(&str, &str)> {
+    let mut parts = env.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() && !env.contains('\0') => Ok((key, value)),
+        _ => bail!(
+            "Invalid environment variable '{}' (Note: expected 'KEY=VALUE', e.g. \
+             'RUSTFLAGS=-C target-cpu=native')",
+            env
+        ),
+    }
+}
+
+/// Runs one build step in `dir` with `envs` added to its environment, and its output
+/// going straight to the terminal.
+fn run_build(
+    dir: &Path,
+    mut command: Command,
+    shown: &str,
+    envs: &[(&str, &str)],
+) -> error::Result<()> {
+    use std::process::Stdio;
+    use error::{ErrorKind, ResultExt};
+
+    log_progress!("Running `{}` in '{}'", shown, dir.display());
+
+    let status = command
+        .current_dir(dir)
+        .envs(envs.iter().cloned())
+        .stdin(Stdio::null())
+        .status()
+        .chain_err(|| format!("Could not run `{}` (Hint: is it installed?)", shown))?;
+    ensure!(
+        status.success(),
+        ErrorKind::BuildFailed(shown.to_string(), status.to_string())
+    );
+
+    Ok(())
+}
+
+/// A build step of a detected build system. npm and yarn are batch files on Windows,
+/// which have to be named in full.
+fn program(args: &[&str]) -> Command {
+    let mut command = if cfg!(windows) && (args[0] == "npm" || args[0] == "yarn") {
+        Command::new(format!("{}.cmd", args[0]))
+    } else {
+        Command::new(args[0])
+    };
+    command.args(&args[1..]);
+
+    command
+}
+
+/// A `--build-command`, run by the platform's shell so it may be a whole pipeline.
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+
+    shell
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BuildSystem, Install};
+    use std::fs;
+    use util::fixture::temp_dir;
+
+    #[test]
+    fn names_stay_in_place() {
+        use clap::App;
+
+        let yaml = load_yaml!("args.yml");
+        let dir = temp_dir("install-names");
+        let url = "https://example.com/team/backend.git";
+        for &name in &["../backend", "bin/backend", "/tmp/backend", "..", ""] {
+            let args = ["better-install", "install", "backend", "--remote", url, "--name", name];
+            let app = App::from_yaml(yaml).get_matches_from(&args[..]);
+            let install = app.subcommand_matches("install").unwrap();
+            assert!(Install::from_subcommand(install, &dir).is_err(), "{}", name);
+        }
+
+        let args = ["better-install", "install", "backend", "--remote", url, "-n", "my-backend"];
+        let app = App::from_yaml(yaml).get_matches_from(&args[..]);
+        let install = Install::from_subcommand(app.subcommand_matches("install").unwrap(), &dir);
+        assert_eq!(install.unwrap().path, dir.join("git/my-backend"));
+    }
+
+    #[test]
+    fn build_system_detection() {
+        let project = |name: &str, files: &[&str]| {
+            let dir = temp_dir(&format!("build-system-{}", name));
+            for file in files {
+                fs::write(dir.join(file), b"").unwrap();
+            }
+
+            BuildSystem::detect(&dir)
+        };
+
+        assert_eq!(project("cargo", &["Cargo.toml"]), Some(BuildSystem::Cargo));
+        assert_eq!(project("npm", &["package.json"]), Some(BuildSystem::Npm));
+        assert_eq!(project("yarn", &["package.json", "yarn.lock"]), Some(BuildSystem::Yarn));
+        assert_eq!(project("make", &["Makefile"]), Some(BuildSystem::Make));
+        assert_eq!(project("gnu-make", &["GNUmakefile"]), Some(BuildSystem::Make));
+        assert_eq!(project("cmake", &["CMakeLists.txt"]), Some(BuildSystem::CMake));
+        assert_eq!(project("none", &["README.md", "yarn.lock"]), None);
+
+        let both = project("cargo-make", &["Makefile", "Cargo.toml", "CMakeLists.txt"]);
+        assert_eq!(both, Some(BuildSystem::Cargo));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_commands_run_in_place() {
+        use super::{run_build, shell};
+        use error::ErrorKind;
+
+        let dir = temp_dir("build-command");
+        run_build(&dir, shell("echo built > out.txt"), "echo built", &[]).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "built\n");
+
+        let err = run_build(&dir, shell("exit 3"), "exit 3", &[]).unwrap_err();
+        match *err.kind() {
+            ErrorKind::BuildFailed(ref command, ref status) => {
+                assert_eq!(command, "exit 3");
+                assert!(status.contains('3'), "{}", status);
+            }
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_environment() {
+        use std::env;
+        use super::{parse_env, run_build, shell};
+
+        let dir = temp_dir("build-env");
+        let envs = [parse_env("NODE_ENV=production").unwrap(), ("EMPTY", "")];
+        let command = "printf '%s,%s,%s' \"$NODE_ENV\" \"${EMPTY-unset}\" \"$PATH\" > env.txt";
+        run_build(&dir, shell(command), "printf", &envs).unwrap();
+
+        // Layered on top of the installer's own environment
+        let path = env::var("PATH").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("env.txt")).unwrap(),
+            format!("production,,{}", path)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn artifact_detection() {
+        use super::executables;
+        use util::tar::set_mode;
+
+        let dir = temp_dir("build-artifacts");
+        let release = dir.join("target/release");
+        fs::create_dir_all(release.join("deps")).unwrap();
+        let files = [
+            ("backend", 0o755),
+            ("backend.d", 0o644),
+            ("libglue.so", 0o755),
+            ("notes.txt", 0o644),
+            ("tool", 0o700),
+        ];
+        for &(file, mode) in &files {
+            fs::write(release.join(file), b"").unwrap();
+            set_mode(&release.join(file), mode).unwrap();
+        }
+
+        let found = BuildSystem::Cargo.artifacts(&dir);
+        assert_eq!(found, vec![release.join("backend"), release.join("tool")]);
+        assert!(BuildSystem::CMake.artifacts(&dir).is_empty());
+        assert!(BuildSystem::Make.artifacts(&dir).is_empty());
+        assert!(executables(&dir.join("missing")).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn artifacts_placed_in_prefix() {
+        use super::place_artifacts;
+
+        let dir = temp_dir("install-prefix");
+        let built = dir.join("built");
+        fs::write(&built, b"v1").unwrap();
+
+        let prefix = dir.join("bin");
+        let installed = place_artifacts(::std::slice::from_ref(&built), &prefix, false).unwrap();
+        assert_eq!(installed.artifacts, vec!["built"]);
+        assert_eq!(fs::read_link(prefix.join("built")).unwrap(), fs::canonicalize(&built).unwrap());
+
+        // A copy replaces the symlink instead of writing through it
+        place_artifacts(::std::slice::from_ref(&built), &prefix, true).unwrap();
+        fs::write(&built, b"v2").unwrap();
+        assert!(!fs::symlink_metadata(prefix.join("built")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(prefix.join("built")).unwrap(), b"v1");
+    }
+
+    #[test]
+    fn install_records() {
+        use std::path::PathBuf;
+        use super::Installed;
+
+        let installed = Installed {
+            prefix: PathBuf::from("/home/me/.scaii/bin"),
+            artifacts: vec!["backend".to_string(), "with space".to_string()],
+        };
+        assert_eq!(Installed::parse(&installed.to_record()).unwrap(), installed);
+
+        assert!(Installed::parse("prefix /bin\n").is_err());
+        assert!(Installed::parse("better-install installed 1\nartifact a\n").is_err());
+        assert!(Installed::parse("better-install installed 1\nprefix /bin\nfile a\n").is_err());
+    }
+
+    #[test]
+    fn env_parsing() {
+        use super::parse_env;
+
+        assert_eq!(parse_env("RUSTFLAGS=-C opt-level=3").unwrap(), ("RUSTFLAGS", "-C opt-level=3"));
+        assert_eq!(parse_env("EMPTY=").unwrap(), ("EMPTY", ""));
+        assert!(parse_env("=value").is_err());
+        assert!(parse_env("NO_VALUE").is_err());
+        assert!(parse_env("NUL=a\0b").is_err());
+    }
+}
@@ -8,3 +8,41 @@ macro_rules! usage_and_exit {
         process::exit(1);
     }};
 }
+
+/// `eprintln!`, with the line also copied to the `--log-file`, if one was given.
+macro_rules! log_eprintln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{}", line);
+        ::util::log::record(&line);
+    }};
+}
+
+/// `println!`, with the line also copied to the `--log-file`, if one was given.
+macro_rules! log_println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        ::util::log::record(&line);
+    }};
+}
+
+/// `print!`, with the text also copied to the `--log-file`, if one was given.
+macro_rules! log_print {
+    ($($arg:tt)*) => {{
+        let text = format!($($arg)*);
+        print!("{}", text);
+        ::util::log::record(&text);
+    }};
+}
+
+/// `log_eprintln!` for progress, which goes to stdout instead with `--progress-on-stdout`.
+macro_rules! log_progress {
+    ($($arg:tt)*) => {{
+        if ::util::log::progress_on_stdout() {
+            log_println!($($arg)*);
+        } else {
+            log_eprintln!($($arg)*);
+        }
+    }};
+}
@@ -10,17 +10,24 @@ extern crate git2;
 extern crate walkdir;
 
 extern crate curl;
+extern crate flate2;
+extern crate glob;
 extern crate remove_dir_all as fs2;
 extern crate zip;
 
-use clap::App;
+use clap::{App, ArgMatches};
 
 // Important! Macros can only be used after they're defined
 // keep this at the top of the imports
 #[macro_use]
 pub(crate) mod macros;
 
+pub(crate) mod bundle;
 pub(crate) mod get;
+pub(crate) mod install;
+pub(crate) mod uninstall;
+pub(crate) mod unzip;
+pub(crate) mod verify;
 
 pub(crate) mod error;
 pub(crate) mod util;
@@ -28,32 +35,305 @@ pub mod constants;
 
 use error::Result;
 
-quick_main!{ || -> Result<i32> {
-    use get::Get;
-    use std::env;
-    use error::{ResultExt,ErrorKind, CLEAN_EXIT};
+fn main() {
+    use std::process;
 
     let yaml = load_yaml!("args.yml");
-    let app = App::from_yaml(yaml)
+    let cli = App::from_yaml(yaml)
         .author(crate_authors!("\n"))
-        .version(crate_version!())
-        .get_matches();
+        .version(crate_version!());
+    // Kept around to generate completions from
+    let app = cli.clone().get_matches();
+
+    process::exit(match run(&app, cli) {
+        Ok(code) => code,
+        Err(ref e) => {
+            print_error(e, app.is_present("json-errors"));
+            1
+        }
+    });
+}
+
+/// Prints the whole error chain to stderr, either the way `quick_main!` does
+/// or as a single line of JSON for tools wrapping the installer.
+fn print_error(err: &error::Error, json: bool) {
+    use error_chain::ChainedError;
+    use std::io::{self, Write};
+
+    let report = if json {
+        format!("{}\n", error::chain_to_json(err))
+    } else {
+        err.display_chain().to_string()
+    };
+
+    write!(io::stderr(), "{}", report).expect("Error writing to stderr");
+    util::log::record(&report);
+}
+
+fn run(app: &ArgMatches, mut cli: App) -> Result<i32> {
+    use get::Get;
+    use install::{FromBundle, Install};
+    use uninstall::Uninstall;
+    use unzip::Unzip;
+    use verify::Verify;
+    use std::env;
+    use std::path::Path;
+    use std::time::Instant;
+    use error::{ResultExt,ErrorKind, CLEAN_EXIT};
 
     let sub_command = app.subcommand();
     let sub_command = (sub_command.0, sub_command.1.unwrap());
 
+    // Global, so it may have been given after the subcommand or the resource instead
+    let resource = sub_command.1.subcommand().1;
+    let log_file = app.value_of("log-file")
+        .or(sub_command.1.value_of("log-file"))
+        .or(resource.and_then(|resource| resource.value_of("log-file")));
+    if let Some(log_file) = log_file {
+        util::log::open(Path::new(log_file), &env::args().collect::<Vec<_>>())?;
+    }
+
+    let sentinel = app.value_of("touch-on-complete")
+        .or(sub_command.1.value_of("touch-on-complete"))
+        .or(resource.and_then(|resource| resource.value_of("touch-on-complete")));
+
     let mut scaii_home = env::home_dir().expect("No home directory present on this user, aborting");
     scaii_home.push(".scaii");
 
     match sub_command {
         ("get", sc) => {
+            if sc.is_present("progress-on-stdout") {
+                ensure!(
+                    sc.value_of("output-format") != Some("env"),
+                    "--progress-on-stdout would mix progress into the `--output-format=env` \
+                     assignments on stdout (Hint: leave progress on stderr, or use \
+                     `--output-format=text`)"
+                );
+                util::log::set_progress_on_stdout(true);
+            }
+
             let cmd = Get::from_subcommand(&sc, &scaii_home).chain_err(|| ErrorKind::GetFailure)?;
-            cmd.get().chain_err(|| ErrorKind::GetFailure)?;
+            let list_deps = sc.subcommand_matches("core").map(|core| core.is_present("list-deps"));
+            if list_deps == Some(true) {
+                log_print!("{}", cmd.describe_core_resources());
+                return Ok(CLEAN_EXIT);
+            }
+
+            if sc.is_present("list-branches") {
+                let branches = cmd.list_branches().chain_err(|| ErrorKind::GetFailure)?;
+                if sc.is_present("json") {
+                    log_println!("{}", get::branches_to_json(&branches));
+                } else {
+                    for (name, _) in &branches {
+                        log_println!("{}", name);
+                    }
+                }
+                return Ok(CLEAN_EXIT);
+            }
+
+            let url = cmd.url();
+            let started = Instant::now();
+            let report_file = sc.value_of("report-file");
+            let report = cmd.get().chain_err(|| ErrorKind::GetFailure).and_then(|report| {
+                if let Some(file) = sc.value_of("bundle") {
+                    bundle::create(&report, url, Path::new(file))
+                        .chain_err(|| ErrorKind::GetFailure)?;
+                    log_progress!("Bundled '{}' into '{}'", report.path.display(), file);
+                }
+                Ok(report)
+            });
+            let report = match report {
+                Ok(report) => report,
+                Err(err) => {
+                    write_report(report_file, Err(&err), started)?;
+                    return Err(err);
+                }
+            };
+            if sc.is_present("resolve-only") {
+                if let Some(ref commit) = report.commit {
+                    log_println!("{}", commit);
+                }
+                write_report(report_file, Ok(&report), started)?;
+                return Ok(CLEAN_EXIT);
+            }
+
+            for warning in &report.warnings {
+                log_eprintln!("Warning: {}", warning);
+            }
+            if sc.value_of("output-format") == Some("env") {
+                log_print!("{}", report.to_env());
+            } else {
+                let done = if report.skipped {
+                    "Already installed"
+                } else if report.tarball {
+                    "Fetched a tarball of"
+                } else {
+                    "Fetched"
+                };
+                match report.commit {
+                    Some(ref commit) => {
+                        log_println!("{} '{}' at {}", done, report.path.display(), commit)
+                    }
+                    None => log_println!("{} '{}'", done, report.path.display()),
+                }
+
+                if sc.is_present("show-changes") {
+                    let (count, shown) = (report.changes_count, report.changes.len());
+                    if let Some(ref previous) = report.previous_commit {
+                        match count {
+                            0 => log_println!("No new commits since {}", &previous[..7]),
+                            _ => log_println!("{} new commit(s) since {}:", count, &previous[..7]),
+                        }
+                    }
+                    for change in &report.changes {
+                        log_println!("  {}", change);
+                    }
+                    if count > shown {
+                        log_println!("  ... and {} more", count - shown);
+                    }
+                }
+            }
+
+            // The report is only written once everything, down to the sentinel, is done
+            let touched = touch_on_complete(sentinel);
+            write_report(report_file, touched.as_ref().map(|_| &report), started)?;
+            touched?;
+        }
+        ("unzip", sc) => {
+            let cmd = Unzip::from_subcommand(sc).chain_err(|| ErrorKind::UnzipFailure)?;
+            cmd.unzip().chain_err(|| ErrorKind::UnzipFailure)?;
+        }
+        ("verify", sc) => {
+            let problems = Verify::from_subcommand(sc, &scaii_home)?.verify();
+            return Ok(Verify::exit_code(&problems));
+        }
+        ("install", sc) => {
+            let bundle = FromBundle::from_subcommand(sc, &scaii_home)
+                .chain_err(|| ErrorKind::InstallFailure)?;
+            match bundle {
+                Some(bundle) => {
+                    let file = bundle.file;
+                    let (manifest, path) = bundle.unpack().chain_err(|| ErrorKind::InstallFailure)?;
+                    match manifest.commit {
+                        Some(ref commit) => log_println!(
+                            "Installed '{}' at {} from '{}'",
+                            path.display(),
+                            commit,
+                            file.display()
+                        ),
+                        None => {
+                            log_println!("Installed '{}' from '{}'", path.display(), file.display())
+                        }
+                    }
+                }
+                None => {
+                    let cmd = Install::from_subcommand(sc, &scaii_home)
+                        .chain_err(|| ErrorKind::InstallFailure)?;
+                    let path = cmd.install().chain_err(|| ErrorKind::InstallFailure)?;
+                    log_println!("Installed '{}'", path.display());
+                }
+            }
+            touch_on_complete(sentinel)?;
+        }
+        ("uninstall", sc) => {
+            let cmd = Uninstall::from_subcommand(sc, &scaii_home)
+                .chain_err(|| ErrorKind::UninstallFailure)?;
+            cmd.uninstall().chain_err(|| ErrorKind::UninstallFailure)?;
+        }
+        ("completions", sc) => {
+            use clap::Shell;
+            use std::io;
+
+            let shell = value_t!(sc, "shell", Shell).unwrap_or_else(|e| e.exit());
+            cli.gen_completions_to(crate_name!(), shell, &mut io::stdout());
         }
-        ("install", _sc) => unimplemented!(),
         ("clean", _sc) => unimplemented!(),
         _ => usage_and_exit!(app),
     };
 
     Ok(CLEAN_EXIT)
-}}
+}
+
+/// Creates or updates the `--touch-on-complete` sentinel, once everything has succeeded.
+fn touch_on_complete(sentinel: Option<&str>) -> Result<()> {
+    use error::ResultExt;
+    use std::path::Path;
+
+    match sentinel {
+        Some(file) => util::touch(Path::new(file))
+            .chain_err(|| format!("Could not touch the sentinel '{}'", file)),
+        None => Ok(()),
+    }
+}
+
+/// Writes the `--report-file` for a `get`, either the report of what it did or the chain of
+/// the error it (or bundling it, or touching the sentinel) failed with.
+fn write_report(
+    file: Option<&str>,
+    outcome: ::std::result::Result<&get::GetReport, &error::Error>,
+    started: ::std::time::Instant,
+) -> Result<()> {
+    use error::ResultExt;
+    use std::path::Path;
+
+    let file = match file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+    let json = match outcome {
+        Ok(report) => report.to_json(started.elapsed()),
+        Err(err) => format!("{{\"status\":\"error\",\"error\":{}}}", error::chain_to_json(err)),
+    };
+
+    util::write_atomically(Path::new(file), format!("{}\n", json).as_bytes())
+        .chain_err(|| format!("Could not write the report to '{}'", file))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn completions_generate() {
+        use clap::{App, Shell};
+
+        // clap only notices arguments it can't resolve once it generates completions
+        let yaml = load_yaml!("args.yml");
+        for shell in &Shell::variants() {
+            let mut script = Vec::new();
+            App::from_yaml(yaml).gen_completions_to(
+                crate_name!(),
+                shell.parse().unwrap(),
+                &mut script,
+            );
+
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("retry-on-http"), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn sentinel_untouched_on_failure() {
+        use clap::App;
+        use std::fs;
+        use util::fixture::temp_dir;
+
+        let dir = temp_dir("touch-on-complete");
+        let (sentinel, bundle) = (dir.join("installed.stamp"), dir.join("missing.tar"));
+        let args = vec![
+            "better-install".to_string(),
+            "install".to_string(),
+            "--from-bundle".to_string(),
+            bundle.display().to_string(),
+            "--touch-on-complete".to_string(),
+            sentinel.display().to_string(),
+        ];
+
+        let yaml = load_yaml!("args.yml");
+        let app = App::from_yaml(yaml).get_matches_from(args);
+        assert!(super::run(&app, App::from_yaml(yaml)).is_err());
+        assert!(!sentinel.exists());
+
+        super::touch_on_complete(sentinel.to_str()).unwrap();
+        assert!(sentinel.exists());
+        fs::remove_file(&sentinel).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use clap::ArgMatches;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use constants::{CORE_NAME, RTS_NAME};
+use error::{self, ResultExt};
+use install::Installed;
+
+/// Removes what `install` put into its prefix for a resource, going by the record it
+/// left under `~/.scaii/installed`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Uninstall<'a> {
+    name: &'a str,
+    record: PathBuf,
+    dry_run: bool,
+}
+
+impl<'a> Uninstall<'a> {
+    pub fn from_subcommand(
+        subcommand: &'a ArgMatches<'a>,
+        scaii_dir: &Path,
+    ) -> error::Result<Self> {
+        // Clap validates required arguments, so this can't be user error
+        let name = match subcommand.value_of("name").unwrap() {
+            name if name.eq_ignore_ascii_case("core") => CORE_NAME,
+            name if name.eq_ignore_ascii_case("rts") => RTS_NAME,
+            name => name,
+        };
+        ensure!(
+            is_file_name(name),
+            "Invalid resource name '{}' (Note: expected the name of its directory, e.g. \
+             'my-backend')",
+            name
+        );
+
+        Ok(Uninstall {
+            name,
+            record: Installed::path(scaii_dir, name),
+            dry_run: subcommand.is_present("dry-run"),
+        })
+    }
+
+    /// Removes the recorded binaries, and the record with them. A binary that is already
+    /// gone is only warned about, and one the record places outside its prefix is never
+    /// touched. Returns how many were removed.
+    pub fn uninstall(self) -> error::Result<usize> {
+        use error::ErrorKind;
+
+        let record = fs::read_to_string(&self.record).chain_err(|| {
+            format!(
+                "Nothing was installed for '{}' (Note: no record at '{}')",
+                self.name,
+                self.record.display()
+            )
+        })?;
+        let installed = Installed::parse(&record)
+            .chain_err(|| ErrorKind::CannotReadError(self.record.display().to_string()))?;
+
+        let outside: Vec<_> = installed
+            .artifacts
+            .iter()
+            .filter(|name| !is_file_name(name))
+            .collect();
+        ensure!(
+            outside.is_empty(),
+            "The install record of '{}' names files outside of '{}', refusing to remove \
+             anything: {:?} (Hint: delete '{}' and remove the binaries by hand)",
+            self.name,
+            installed.prefix.display(),
+            outside,
+            self.record.display()
+        );
+
+        let mut removed = 0;
+        for artifact in &installed.artifacts {
+            let path = installed.prefix.join(artifact);
+            if path.symlink_metadata().is_err() {
+                log_eprintln!("Warning: '{}' was already removed", path.display());
+            } else if self.dry_run {
+                log_println!("Would remove '{}'", path.display());
+                removed += 1;
+            } else {
+                fs::remove_file(&path)
+                    .chain_err(|| format!("Could not remove '{}'", path.display()))?;
+                log_println!("Removed '{}'", path.display());
+                removed += 1;
+            }
+        }
+
+        if !self.dry_run {
+            fs::remove_file(&self.record)
+                .chain_err(|| format!("Could not remove '{}'", self.record.display()))?;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Whether `name` is a single plain path component, so joining it stays in the directory.
+pub fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => !name.contains('/') && !name.contains('\\'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_file_name, Uninstall};
+    use std::fs;
+    use std::path::Path;
+    use install::Installed;
+    use util::fixture::temp_dir;
+
+    fn uninstall(scaii_dir: &Path, dry_run: bool) -> Uninstall<'static> {
+        Uninstall {
+            name: "backend",
+            record: Installed::path(scaii_dir, "backend"),
+            dry_run,
+        }
+    }
+
+    #[test]
+    fn file_names() {
+        assert!(is_file_name("backend"));
+        assert!(is_file_name("my backend.exe"));
+        assert!(!is_file_name("../backend"));
+        assert!(!is_file_name("bin/backend"));
+        assert!(!is_file_name("/usr/bin/backend"));
+        assert!(!is_file_name(".."));
+        assert!(!is_file_name("."));
+        assert!(!is_file_name(""));
+    }
+
+    #[test]
+    fn removes_recorded_binaries() {
+        let scaii_dir = temp_dir("uninstall");
+        let prefix = scaii_dir.join("bin");
+        fs::create_dir_all(&prefix).unwrap();
+        fs::write(prefix.join("backend"), b"").unwrap();
+        fs::write(prefix.join("other"), b"").unwrap();
+
+        let record = Installed::path(&scaii_dir, "backend");
+        fs::create_dir_all(record.parent().unwrap()).unwrap();
+        let installed = Installed {
+            prefix: prefix.clone(),
+            artifacts: vec!["backend".to_string(), "deleted-by-hand".to_string()],
+        };
+        fs::write(&record, installed.to_record()).unwrap();
+
+        assert_eq!(uninstall(&scaii_dir, true).uninstall().unwrap(), 1);
+        assert!(prefix.join("backend").is_file());
+        assert!(record.is_file());
+
+        assert_eq!(uninstall(&scaii_dir, false).uninstall().unwrap(), 1);
+        assert!(!prefix.join("backend").exists());
+        assert!(prefix.join("other").is_file());
+        assert!(!record.exists());
+
+        assert!(uninstall(&scaii_dir, false).uninstall().is_err());
+    }
+
+    #[test]
+    fn refuses_files_outside_prefix() {
+        let scaii_dir = temp_dir("uninstall-outside");
+        let outside = scaii_dir.join("keep");
+        fs::write(&outside, b"").unwrap();
+
+        let record = Installed::path(&scaii_dir, "backend");
+        fs::create_dir_all(record.parent().unwrap()).unwrap();
+        let installed = Installed {
+            prefix: scaii_dir.join("bin"),
+            artifacts: vec!["../keep".to_string()],
+        };
+        fs::write(&record, installed.to_record()).unwrap();
+
+        assert!(uninstall(&scaii_dir, false).uninstall().is_err());
+        assert!(outside.is_file());
+        assert!(record.is_file());
+    }
+}
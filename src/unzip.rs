@@ -0,0 +1,157 @@
+use clap::ArgMatches;
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+
+use constants::DEFAULT_MAX_ARCHIVE_ENTRIES;
+use error::{self, ResultExt};
+
+use util::{self, split_zip, CdManager, UnzipOptions};
+
+/// Extracts a local archive, mostly useful for debugging the dependency
+/// archives `get core` fetches. A split archive is joined from the `.z01`, `.z02`...
+/// volumes next to it first.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Unzip<'a> {
+    archive: &'a str,
+    dest: PathBuf,
+    into: bool,
+    only: Option<Pattern>,
+    exclude: Vec<Pattern>,
+    dir_mode: Option<u32>,
+    best_effort: bool,
+    max_entries: usize,
+}
+
+impl<'a> Unzip<'a> {
+    pub fn from_subcommand(subcommand: &'a ArgMatches<'a>) -> error::Result<Self> {
+        let only = match subcommand.value_of("only") {
+            Some(only) => Some(Pattern::new(only)?),
+            None => None,
+        };
+
+        let exclude = match subcommand.values_of("exclude-pattern") {
+            Some(patterns) => patterns.map(Pattern::new).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        let dir_mode = match subcommand.value_of("dir-permissions") {
+            Some(mode) => Some(util::parse_dir_mode(mode)?),
+            None => None,
+        };
+
+        let max_entries = match subcommand.value_of("max-archive-entries") {
+            Some(max) => max.parse().chain_err(|| {
+                format!("Invalid --max-archive-entries '{}' (Hint: expected a count)", max)
+            })?,
+            None => DEFAULT_MAX_ARCHIVE_ENTRIES,
+        };
+
+        Ok(Unzip {
+            // Clap validates required arguments, so this can't be user error
+            archive: subcommand.value_of("archive").unwrap(),
+            dest: PathBuf::from(subcommand.value_of("dest").unwrap_or(".")),
+            into: subcommand.is_present("into"),
+            only,
+            exclude,
+            dir_mode,
+            best_effort: subcommand.is_present("best-effort"),
+            max_entries,
+        })
+    }
+
+    pub fn unzip(mut self) -> error::Result<()> {
+        use std::fs::File;
+        use std::io::Read;
+        use std::sync::atomic::AtomicBool;
+        use error::ErrorKind;
+
+        let read = |path: &Path| -> error::Result<Vec<u8>> {
+            let mut buf = Vec::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_end(&mut buf))
+                .chain_err(|| ErrorKind::CannotReadError(path.display().to_string()))?;
+            Ok(buf)
+        };
+
+        let volumes = volume_paths(Path::new(self.archive));
+        let mut buf = read(&volumes[volumes.len() - 1])?;
+        // An unrelated `.z01` next to a whole archive is left out of it
+        if volumes.len() > 1 && split_zip::is_split_volume(&buf) {
+            log_progress!("Joining {} volumes of '{}'", volumes.len(), self.archive);
+
+            let mut split = volumes[..volumes.len() - 1]
+                .iter()
+                .map(|volume| read(volume))
+                .collect::<error::Result<Vec<_>>>()?;
+            split.push(buf);
+            buf = split_zip::join_volumes(&split)
+                .chain_err(|| format!("Could not join the volumes of '{}'", self.archive))?;
+        }
+
+        log_println!(
+            "Extracting '{}' into '{}'",
+            self.archive,
+            self.dest.display()
+        );
+
+        let options = UnzipOptions {
+            into: self.into,
+            filter: self.only.as_ref(),
+            exclude: &self.exclude,
+            dir_mode: self.dir_mode,
+            best_effort: self.best_effort,
+            max_entries: self.max_entries,
+            ..UnzipOptions::default()
+        };
+
+        util::unzip(
+            &buf,
+            CdManager::new(&mut self.dest),
+            &options,
+            &AtomicBool::new(false),
+        )
+    }
+}
+
+/// The volumes of the split archive `archive` belongs to, from `.z01` up with the `.zip`
+/// last, given the `.zip` or any of the others. Anything without numbered volumes next to
+/// it is its own only volume.
+fn volume_paths(archive: &Path) -> Vec<PathBuf> {
+    use std::ffi::OsStr;
+
+    let extension = archive.extension().and_then(OsStr::to_str).unwrap_or("").to_lowercase();
+    let is_volume = extension == "zip"
+        || (extension.len() >= 3 && extension.starts_with('z')
+            && extension[1..].chars().all(|c| c.is_ascii_digit()));
+
+    let numbered = |n: u32| archive.with_extension(format!("z{:02}", n));
+    if !is_volume || !numbered(1).is_file() {
+        return vec![archive.to_path_buf()];
+    }
+
+    let mut volumes: Vec<_> = (1..).map(numbered).take_while(|volume| volume.is_file()).collect();
+    volumes.push(archive.with_extension("zip"));
+
+    volumes
+}
+
+#[cfg(test)]
+mod test {
+    use super::volume_paths;
+    use std::fs;
+    use util::fixture::temp_dir;
+
+    #[test]
+    fn finds_volumes() {
+        let dir = temp_dir("split-volumes");
+        for name in &["big.z01", "big.z02", "big.zip", "whole.zip", "notes.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let split = vec![dir.join("big.z01"), dir.join("big.z02"), dir.join("big.zip")];
+
+        assert_eq!(volume_paths(&dir.join("big.zip")), split);
+        assert_eq!(volume_paths(&dir.join("big.z02")), split);
+        assert_eq!(volume_paths(&dir.join("whole.zip")), vec![dir.join("whole.zip")]);
+        assert_eq!(volume_paths(&dir.join("notes.txt")), vec![dir.join("notes.txt")]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Mutex;
+
+/// A `BufferPool` hands out download buffers and takes them back once the caller is done,
+/// so big buffers are allocated once and then recycled, even between threads.
+///
+/// Buffers are always handed out empty. A buffer is only allocated when every buffer
+/// the pool has made is checked out, and then with the pool's capacity so it's large
+/// enough for any of the core dependencies without growing.
+#[derive(Debug)]
+pub struct BufferPool {
+    capacity: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Starts an empty pool whose buffers are allocated with room for `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        BufferPool {
+            capacity,
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Checks out an empty buffer, reusing a returned one if there is any.
+    pub fn take(&self) -> Vec<u8> {
+        let reused = self.free.lock().unwrap().pop();
+
+        reused.unwrap_or_else(|| Vec::with_capacity(self.capacity))
+    }
+
+    /// Returns a buffer to the pool for the next `take`.
+    pub fn give(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        self.free.lock().unwrap().push(buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BufferPool;
+
+    #[test]
+    fn buffers_are_recycled() {
+        let pool = BufferPool::new(1024);
+
+        let mut buf = pool.take();
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 1024);
+
+        buf.extend_from_slice(b"downloaded");
+        let ptr = buf.as_ptr();
+        pool.give(buf);
+
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let pool = Arc::new(BufferPool::new(16));
+        let workers: Vec<_> = (0..4u8)
+            .map(|i| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut buf = pool.take();
+                        assert!(buf.is_empty());
+                        buf.push(i);
+                        pool.give(buf);
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // Never more buffers than workers holding one at the same time
+        assert!(pool.free.lock().unwrap().len() <= 4);
+    }
+}
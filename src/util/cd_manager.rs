@@ -1,3 +1,4 @@
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use error;
 
@@ -7,11 +8,13 @@ use error;
 /// It takes a reference to a `PathBuf` and, upon going out of scope, will manually `pop`
 /// all elements of the `PathBuf` off that were added during its life.
 ///
-/// The only supported operations are `push` or `pop`, more complex operations such as
-/// cannot easily be managed.
+/// The only supported operations are `push`, `push_guard`, `pop` and `replace_tail` (a `pop`
+/// followed by a `push`), more complex operations such as `set_file_name` cannot easily be managed.
 ///
 /// Note that the `CdManager` uses a path's `Components` to determine how many times
-/// to call `pop`, so this may cause some inconsistency if your path includes `.`.
+/// to call `pop`, so this may cause some inconsistency if your path includes `.`. Pushing
+/// an absolute path replaces the whole `PathBuf`, which can't be popped back off either.
+/// Debug builds check the depth after every operation and panic on either mistake.
 ///
 /// A `CdManager` implements `AsRef<Path>` so it may be used anywhere a `Path` is needed.
 #[derive(Debug)]
@@ -52,8 +55,49 @@ impl<'a> CdManager<'a> {
     /// assert_eq!(path, p2);
     /// ```
     pub fn push<P: AsRef<Path>>(&mut self, path: P) {
-        self.added_depth += path.as_ref().components().count();
+        let (before, pushed) = (depth(self.path), path.as_ref().components().count());
+
+        self.added_depth += pushed;
         self.path.push(path);
+
+        debug_assert_eq!(
+            depth(self.path),
+            before + pushed,
+            "Pushing onto a CdManager left '{}' at the wrong depth (Note: absolute paths and \
+             `.` can't be popped back off)",
+            self.path.display()
+        );
+    }
+
+    /// Pushes a `Path` onto the `PathBuf` like `push`, but returns a `PushGuard` that undoes
+    /// it as soon as the guard is dropped rather than when the `CdManager` is.
+    ///
+    /// Dropping the guard brings the manager back to the depth it had before this push,
+    /// so anything pushed through the guard in the meantime is popped along with it.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    ///
+    /// let mut path = PathBuf::from("a/path".to_string());
+    /// let mut cd = CdManager::new(&mut path);
+    ///
+    /// for dir in &["src", "js"] {
+    ///     let mut outer = cd.push_guard(dir);
+    ///     {
+    ///         let inner = outer.push_guard("binary");
+    ///         assert_eq!(inner, PathBuf::from("a/path").join(dir).join("binary"));
+    ///     } // Pops "binary"
+    ///
+    ///     assert_eq!(outer, PathBuf::from("a/path").join(dir));
+    /// } // Pops `dir`
+    ///
+    /// assert_eq!(cd, PathBuf::from("a/path"));
+    /// ```
+    pub fn push_guard<'m, P: AsRef<Path>>(&'m mut self, path: P) -> PushGuard<'m, 'a> {
+        let depth = self.added_depth;
+        self.push(path);
+
+        PushGuard { cd: self, depth }
     }
 
     /// Pops a single link from the underlying `PathBuf`.
@@ -84,9 +128,49 @@ impl<'a> CdManager<'a> {
             "Cannot pop off CdManager, going below original directory"
         );
 
+        let before = depth(self.path);
         self.added_depth -= 1;
         self.path.pop();
 
+        debug_assert_eq!(
+            depth(self.path) + 1,
+            before,
+            "Popping a CdManager left '{}' at the wrong depth",
+            self.path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Replaces the last component pushed with `name`, like `PathBuf::set_file_name` but
+    /// keeping track of the depth to pop. This will return an error (and leave the path
+    /// alone) if nothing has been pushed to replace.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    ///
+    /// let mut path = PathBuf::from("a/path".to_string());
+    /// let mut p2 = path.clone();
+    /// {
+    ///     let mut cd = CdManager::new(&mut p2);
+    ///     assert!(cd.replace_tail("js").is_err());
+    ///
+    ///     cd.push("protobuf-3.5.1/src");
+    ///     cd.replace_tail("js").unwrap();
+    ///     assert_eq!(cd, PathBuf::from("a/path/protobuf-3.5.1/js"));
+    /// } // Pops "js" and "protobuf-3.5.1"
+    ///
+    /// assert_eq!(path, p2);
+    /// ```
+    pub fn replace_tail<P: AsRef<Path>>(&mut self, name: P) -> error::Result<()> {
+        ensure!(
+            self.added_depth > 0,
+            "Cannot replace the tail of a CdManager with nothing pushed"
+        );
+
+        self.pop()?;
+        self.push(name);
+
         Ok(())
     }
 
@@ -124,6 +208,21 @@ impl<'a> CdManager<'a> {
     pub fn clone_inner(&self) -> PathBuf {
         self.path.clone()
     }
+
+    /// The current path with `path` pushed onto it, leaving the manager untouched.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    ///
+    /// let mut path = PathBuf::from("a/path".to_string());
+    /// let cd = CdManager::new(&mut path);
+    ///
+    /// assert_eq!(cd.join("sibling"), PathBuf::from("a/path/sibling"));
+    /// assert_eq!(cd, PathBuf::from("a/path"));
+    /// ```
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
 }
 
 impl<'a, P: AsRef<Path>> PartialEq<P> for CdManager<'a> {
@@ -142,9 +241,12 @@ impl<'a> PartialEq<CdManager<'a>> for PathBuf {
 
 impl<'a> Drop for CdManager<'a> {
     fn drop(&mut self) {
+        let before = depth(self.path);
         for _ in 0..self.added_depth {
             self.path.pop();
         }
+
+        check_dropped_depth(self.path, before.saturating_sub(self.added_depth));
     }
 }
 
@@ -154,6 +256,75 @@ impl<'a> AsRef<Path> for CdManager<'a> {
     }
 }
 
+/// Undoes a `CdManager::push_guard` when dropped. It dereferences to the `CdManager`, so
+/// it can be pushed onto (or guarded again) in the meantime.
+#[derive(Debug)]
+pub struct PushGuard<'m, 'a: 'm> {
+    cd: &'m mut CdManager<'a>,
+    /// The manager's `added_depth` before the guarded push
+    depth: usize,
+}
+
+impl<'m, 'a> Deref for PushGuard<'m, 'a> {
+    type Target = CdManager<'a>;
+
+    fn deref(&self) -> &CdManager<'a> {
+        self.cd
+    }
+}
+
+impl<'m, 'a> DerefMut for PushGuard<'m, 'a> {
+    fn deref_mut(&mut self) -> &mut CdManager<'a> {
+        self.cd
+    }
+}
+
+impl<'m, 'a, P: AsRef<Path>> PartialEq<P> for PushGuard<'m, 'a> {
+    fn eq(&self, other: &P) -> bool {
+        *self.cd == *other
+    }
+}
+
+impl<'m, 'a> AsRef<Path> for PushGuard<'m, 'a> {
+    fn as_ref(&self) -> &Path {
+        self.cd.as_ref()
+    }
+}
+
+impl<'m, 'a> Drop for PushGuard<'m, 'a> {
+    fn drop(&mut self) {
+        // Anything popped below the guarded push through the manager is already gone
+        let before = depth(self.cd.path);
+        let popped = self.cd.added_depth.saturating_sub(self.depth);
+        while self.cd.added_depth > self.depth {
+            self.cd.added_depth -= 1;
+            self.cd.path.pop();
+        }
+
+        check_dropped_depth(self.cd.path, before.saturating_sub(popped));
+    }
+}
+
+/// How many components deep `path` is, as `CdManager` counts them.
+fn depth(path: &Path) -> usize {
+    path.components().count()
+}
+
+/// Checks what dropping a `CdManager` or `PushGuard` popped back to in debug builds,
+/// unless it's being dropped by a panic already, which a second one would abort.
+fn check_dropped_depth(path: &Path, expected: usize) {
+    use std::thread;
+
+    if cfg!(debug_assertions) && !thread::panicking() {
+        assert_eq!(
+            depth(path),
+            expected,
+            "Dropping a CdManager left '{}' at the wrong depth",
+            path.display()
+        );
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::CdManager;
@@ -203,6 +374,94 @@ mod test {
         assert_eq!(p2, path);
     }
 
+    #[test]
+    fn cd_manager_replace_tail() {
+        let path = PathBuf::from("a/path/to/something".to_string());
+        let mut p2 = path.clone();
+
+        {
+            let mut cd_manager = CdManager::new(&mut p2);
+            assert!(cd_manager.replace_tail("js").is_err());
+            assert_eq!(path, cd_manager);
+
+            cd_manager.push("protobuf-3.5.1/src");
+            cd_manager.replace_tail("js/binary").unwrap();
+
+            assert_eq!(path.join("protobuf-3.5.1/js/binary"), cd_manager);
+            assert_eq!(cd_manager.added_depth, 3);
+        }
+
+        assert_eq!(p2, path);
+    }
+
+    #[test]
+    fn cd_manager_push_guard() {
+        let path = PathBuf::from("a/path/to/something".to_string());
+        let mut p2 = path.clone();
+
+        {
+            let mut cd_manager = CdManager::new(&mut p2);
+            cd_manager.push("kept");
+
+            for dir in &["abc/def", "ghi"] {
+                let mut guard = cd_manager.push_guard(dir);
+                guard.push("extra");
+                {
+                    let inner = guard.push_guard("inner");
+                    assert_eq!(inner, path.join("kept").join(dir).join("extra/inner"));
+                }
+
+                assert_eq!(guard, path.join("kept").join(dir).join("extra"));
+            }
+
+            assert_eq!(path.join("kept"), cd_manager);
+            assert_eq!(cd_manager.added_depth, 1);
+        }
+
+        assert_eq!(p2, path);
+    }
+
+    #[test]
+    fn cd_manager_join() {
+        let path = PathBuf::from("a/path/to/something".to_string());
+        let mut p2 = path.clone();
+
+        {
+            let mut cd_manager = CdManager::new(&mut p2);
+            cd_manager.push("abc");
+
+            assert_eq!(cd_manager.join("def/ghi"), path.join("abc/def/ghi"));
+            assert_eq!(path.join("abc"), cd_manager);
+            assert_eq!(cd_manager.added_depth, 1);
+        }
+
+        assert_eq!(p2, path);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "wrong depth")]
+    fn cd_manager_catches_absolute_push() {
+        use std::env;
+
+        let mut path = PathBuf::from("a/path");
+        let mut cd_manager = CdManager::new(&mut path);
+
+        // Replaces "a/path" outright, so popping it would leave neither behind
+        cd_manager.push(env::temp_dir());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "wrong depth")]
+    fn cd_manager_catches_cur_dir_push() {
+        let mut path = PathBuf::from("a/path");
+        let mut cd_manager = CdManager::new(&mut path);
+
+        // Counted as a component, but `a/path/.` is no deeper than `a/path`
+        cd_manager.push(".");
+    }
+
     #[test]
     fn cd_manager_error() {
         let mut path = PathBuf::from("a/path/to/something".to_string());
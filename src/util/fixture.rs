@@ -0,0 +1,355 @@
+//! Test-only helpers for exercising downloads and extraction without the network.
+//!
+//! `FixtureServer` is a tiny local HTTP server that answers requests from a script of
+//! `Fixture` responses, which lets tests control status codes, headers, delays, and
+//! truncated bodies. `zip_fixture` builds small archives in memory for `unzip` tests.
+//!
+//! The server speaks just enough HTTP/1.1 for `curl` and closes the connection after
+//! every response, which is what makes simulating a dropped transfer possible.
+
+// Not every test needs every knob
+#![allow(dead_code)]
+
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A single scripted response for a `FixtureServer`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Fixture {
+    status: u16,
+    body: Vec<u8>,
+    headers: Vec<(String, String)>,
+    delay: Duration,
+    truncate_at: Option<usize>,
+    stall: Option<(usize, Duration)>,
+}
+
+impl Fixture {
+    /// A `200 OK` response with the given body.
+    pub fn ok<B: Into<Vec<u8>>>(body: B) -> Self {
+        Fixture::status(200, body)
+    }
+
+    /// A response with an arbitrary status code.
+    pub fn status<B: Into<Vec<u8>>>(status: u16, body: B) -> Self {
+        Fixture {
+            status,
+            body: body.into(),
+            headers: Vec::new(),
+            delay: Duration::from_millis(0),
+            truncate_at: None,
+            stall: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Waits before responding at all.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Advertises the full body length but hangs up after `at` bytes,
+    /// as a dropped connection would.
+    pub fn truncated(mut self, at: usize) -> Self {
+        self.truncate_at = Some(at);
+        self
+    }
+
+    /// Sends the first `at` bytes of the body, then nothing for `pause`,
+    /// as a dying connection would.
+    pub fn stalled(mut self, at: usize, pause: Duration) -> Self {
+        self.stall = Some((at, pause));
+        self
+    }
+}
+
+/// The parts of a received request tests may want to assert on.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RecordedRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RecordedRequest {
+    /// The value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| &*header.1)
+    }
+}
+
+/// A local HTTP server that answers each request with the next `Fixture` in its script,
+/// repeating the last one once the script runs out. It shuts down when dropped.
+pub struct FixtureServer {
+    port: u16,
+    stop: Arc<AtomicBool>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FixtureServer {
+    pub fn start(fixtures: Vec<Fixture>) -> Self {
+        assert!(!fixtures.is_empty(), "A fixture server needs at least one response");
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not start fixture server");
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let thread = {
+            let (stop, requests) = (stop.clone(), requests.clone());
+            thread::spawn(move || serve(&listener, &fixtures, &stop, &requests))
+        };
+
+        FixtureServer {
+            port,
+            stop,
+            requests,
+            thread: Some(thread),
+        }
+    }
+
+    /// The URL of `path` on this server, `path` should start with a `/`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The number of requests received so far.
+    pub fn hits(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+impl Drop for FixtureServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(
+    listener: &TcpListener,
+    fixtures: &[Fixture],
+    stop: &AtomicBool,
+    requests: &Mutex<Vec<RecordedRequest>>,
+) {
+    use std::io::ErrorKind;
+
+    while !stop.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        let request = match read_request(&stream) {
+            Some(request) => request,
+            None => continue,
+        };
+
+        let hit = {
+            let mut requests = requests.lock().unwrap();
+            requests.push(request);
+            requests.len() - 1
+        };
+
+        // The client hanging up early is expected for some fixtures
+        let _ = respond(stream, &fixtures[hit.min(fixtures.len() - 1)]);
+    }
+}
+
+/// Reads a request head, requests are assumed to be bodiless `GET`s.
+fn read_request(stream: &TcpStream) -> Option<RecordedRequest> {
+    use std::io::{BufRead, BufReader};
+
+    stream.set_nonblocking(false).ok()?;
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let url = line.split_whitespace().nth(1)?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Some(RecordedRequest { url, headers })
+}
+
+fn respond(mut stream: TcpStream, fixture: &Fixture) -> ::std::io::Result<()> {
+    use std::io::Write;
+
+    thread::sleep(fixture.delay);
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        fixture.status,
+        reason(fixture.status),
+        fixture.body.len()
+    );
+    for (name, value) in &fixture.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let sent = match fixture.truncate_at {
+        Some(at) => &fixture.body[..at.min(fixture.body.len())],
+        None => &fixture.body[..],
+    };
+
+    stream.write_all(head.as_bytes())?;
+    if let Some((at, pause)) = fixture.stall {
+        let (before, after) = sent.split_at(at.min(sent.len()));
+        stream.write_all(before)?;
+        stream.flush()?;
+        thread::sleep(pause);
+        stream.write_all(after)?;
+    } else {
+        stream.write_all(sent)?;
+    }
+    stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Builds a zip archive in memory. Entries whose names end in `/` are added as
+/// directories and their contents are ignored.
+pub fn zip_fixture(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let entries: Vec<_> = entries.iter().map(|&(name, contents)| (name, contents, None)).collect();
+
+    zip_fixture_with_modes(&entries)
+}
+
+/// Like `zip_fixture`, but entries with a mode record it as their Unix permissions.
+pub fn zip_fixture_with_modes(entries: &[(&str, &[u8], Option<u32>)]) -> Vec<u8> {
+    use std::io::{Cursor, Write};
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    for &(name, contents, mode) in entries {
+        let options = match mode {
+            Some(mode) => FileOptions::default().unix_permissions(mode),
+            None => FileOptions::default(),
+        };
+
+        if name.ends_with('/') {
+            zip.add_directory(name, options).unwrap();
+        } else {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+    }
+
+    zip.finish().unwrap().into_inner()
+}
+
+/// Creates a fresh, empty directory under the system temp directory for a single test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    use std::{env, fs};
+    use fs2;
+
+    let dir = env::temp_dir().join(format!("better-install-test-{}", name));
+    if dir.exists() {
+        fs2::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Splits a zip archive made by `zip_fixture` into volumes of `size` bytes the way
+/// `zip -s` would, except that the last volume is grown to hold the whole end of central
+/// directory record and whatever comes after it.
+pub fn split_zip_fixture(archive: &[u8], size: usize) -> Vec<Vec<u8>> {
+    let get = |buf: &[u8], pos: usize, len: usize| {
+        (0..len).fold(0, |value, i| value | u32::from(buf[pos + i]) << (8 * i))
+    };
+    let set = |buf: &mut Vec<u8>, pos: usize, len: usize, value: u32| {
+        for i in 0..len {
+            buf[pos + i] = (value >> (8 * i)) as u8;
+        }
+    };
+
+    let mut split = b"PK\x07\x08".to_vec();
+    split.extend_from_slice(archive);
+    // Positions in the split stream, as the volume and the offset into it
+    let locate = |pos: u32| (pos / size as u32, pos % size as u32);
+
+    let end = (0..split.len() - 21)
+        .rev()
+        .find(|&pos| split[pos..].starts_with(b"PK\x05\x06"))
+        .expect("Not a zip archive");
+    let entries = get(&split, end + 10, 2);
+    let directory = get(&split, end + 16, 4) + 4;
+
+    let mut pos = directory as usize;
+    for _ in 0..entries {
+        let (disk, offset) = locate(get(&split, pos + 42, 4) + 4);
+        set(&mut split, pos + 34, 2, disk);
+        set(&mut split, pos + 42, 4, offset);
+        pos += 46 + (get(&split, pos + 28, 2) + get(&split, pos + 30, 2)
+            + get(&split, pos + 32, 2)) as usize;
+    }
+
+    let last = end / size;
+    let (directory_disk, directory_offset) = locate(directory);
+    set(&mut split, end + 4, 2, last as u32);
+    set(&mut split, end + 6, 2, directory_disk);
+    set(&mut split, end + 16, 4, directory_offset);
+
+    let mut volumes: Vec<_> = split.chunks(size).take(last).map(<[u8]>::to_vec).collect();
+    volumes.push(split[last * size..].to_vec());
+
+    volumes
+}
@@ -0,0 +1,159 @@
+//! Checking the GPG signature of a commit or tag the way `git verify-commit` and
+//! `git verify-tag` do, except against a keyring of our choosing: the signature is cut out
+//! of the raw object and the rest of it is checked with `gpgv`.
+//!
+//! A signed tag has its armored signature appended to the message. A signed commit has it
+//! in a `gpgsig` header, whose continuation lines start with a space, and what was signed
+//! is the commit without that header.
+
+use std::path::Path;
+
+const SIGNATURE_START: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// What a signature in a raw commit or tag object covers, and the signature itself.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Signed {
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Splits a raw commit object into what was signed and its signature, or `None` if it
+/// isn't signed.
+pub fn split_signed_commit(object: &[u8]) -> Option<Signed> {
+    let header_end = find(object, b"\n\n").map_or(object.len(), |end| end + 1);
+
+    let (mut payload, mut signature) = (Vec::with_capacity(object.len()), Vec::new());
+    let mut in_signature = false;
+    for line in object[..header_end].split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+        if line.starts_with(b"gpgsig ") && signature.is_empty() {
+            in_signature = true;
+            signature.extend_from_slice(&line[b"gpgsig ".len()..]);
+        } else if in_signature && line.starts_with(b" ") {
+            signature.push(b'\n');
+            signature.extend_from_slice(&line[1..]);
+        } else {
+            in_signature = false;
+            payload.extend_from_slice(line);
+            payload.push(b'\n');
+        }
+    }
+    payload.extend_from_slice(&object[header_end..]);
+
+    if signature.is_empty() {
+        return None;
+    }
+    signature.push(b'\n');
+
+    Some(Signed { payload, signature })
+}
+
+/// Splits a raw tag object into what was signed and its signature, or `None` if it
+/// isn't signed.
+pub fn split_signed_tag(object: &[u8]) -> Option<Signed> {
+    let mut start = None;
+    let mut from = 0;
+    while let Some(found) = find(&object[from..], SIGNATURE_START.as_bytes()) {
+        let at = from + found;
+        if at == 0 || object[at - 1] == b'\n' {
+            start = Some(at);
+        }
+        from = at + 1;
+    }
+
+    start.map(|start| Signed {
+        payload: object[..start].to_vec(),
+        signature: object[start..].to_vec(),
+    })
+}
+
+/// Checks `signed` with `gpgv`, against `keyring` or otherwise `gpgv`'s default of
+/// `~/.gnupg/trustedkeys.kbx`. The signature is written to `scratch` for it, and removed
+/// again. Why it failed is the error, as `gpgv` put it.
+pub fn gpgv(signed: &Signed, keyring: Option<&Path>, scratch: &Path) -> Result<(), String> {
+    use std::fs;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fs::write(scratch, &signed.signature)
+        .map_err(|err| format!("could not write '{}': {}", scratch.display(), err))?;
+
+    let mut gpgv = Command::new("gpgv");
+    if let Some(keyring) = keyring {
+        gpgv.arg("--keyring").arg(keyring);
+    }
+    let checked = gpgv
+        .arg(scratch)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            // `gpgv` may give up before reading everything, which is reported below
+            let _ = child.stdin.take().unwrap().write_all(&signed.payload);
+            child.wait_with_output()
+        });
+    let _ = fs::remove_file(scratch);
+
+    match checked {
+        Ok(ref output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason: Vec<_> = stderr.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+            Err(match reason.last() {
+                Some(reason) => reason.trim_start_matches("gpgv: ").to_string(),
+                None => format!("gpgv failed with {}", output.status),
+            })
+        }
+        Err(err) => Err(format!("could not run gpgv: {} (Hint: is GnuPG installed?)", err)),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_signed_commit, split_signed_tag};
+
+    const COMMIT: &str = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                          author A U Thor <a@example.com> 1500000000 +0000\n\
+                          committer A U Thor <a@example.com> 1500000000 +0000\n";
+
+    #[test]
+    fn signed_commits() {
+        let signed = format!(
+            "{}gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEz\n -----END PGP SIGNATURE-----\n\
+             \nRelease 1.0\n\ngpgsig in the message is left alone\n",
+            COMMIT
+        );
+
+        let split = split_signed_commit(signed.as_bytes()).unwrap();
+        assert_eq!(
+            String::from_utf8(split.payload).unwrap(),
+            format!("{}\nRelease 1.0\n\ngpgsig in the message is left alone\n", COMMIT)
+        );
+        assert_eq!(
+            String::from_utf8(split.signature).unwrap(),
+            "-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----\n"
+        );
+
+        let unsigned = format!("{}\ngpgsig -----BEGIN PGP SIGNATURE-----\n", COMMIT);
+        assert_eq!(split_signed_commit(unsigned.as_bytes()), None);
+    }
+
+    #[test]
+    fn signed_tags() {
+        let payload = "object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype commit\ntag v1.0\n\
+                       tagger A U Thor <a@example.com> 1500000000 +0000\n\n\
+                       Quoting -----BEGIN PGP SIGNATURE----- mid-line\n";
+        let signature = "-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----\n";
+
+        let split = split_signed_tag(format!("{}{}", payload, signature).as_bytes()).unwrap();
+        assert_eq!(split.payload, payload.as_bytes());
+        assert_eq!(split.signature, signature.as_bytes());
+
+        assert_eq!(split_signed_tag(payload.as_bytes()), None);
+    }
+}
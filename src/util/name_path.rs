@@ -1,5 +1,27 @@
 use std::path::{Path, PathBuf};
 
+/// How the directory a named install goes in is derived from its name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum OutputLayout {
+    /// `<scaii home>/git/<name>`
+    Nested,
+    /// `<scaii home>/<name>`
+    Flat,
+    /// `<scaii home>/git/<owner>/<name>`, with the owner taken from the URL
+    ByOwner,
+}
+
+impl OutputLayout {
+    /// How far below the SCAII home a named install in this layout is at least, the
+    /// default `--depth-limit` for replacing one.
+    pub fn depth_limit(self) -> usize {
+        match self {
+            OutputLayout::Flat => 1,
+            OutputLayout::Nested | OutputLayout::ByOwner => ::constants::DEFAULT_DEPTH_LIMIT,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum NameOrPath<'a> {
     Name(&'a str),
@@ -22,15 +44,165 @@ impl<'a> NameOrPath<'a> {
         }
     }
 
+    /// Guesses a repository's name from its URL, the way `git clone` picks a directory:
+    /// the last path segment without any `.git` suffix, so both
+    /// `https://github.com/team/my-backend.git` and `git@github.com:team/my-backend/`
+    /// give `my-backend`.
+    pub fn name_from_url(url: &'a str) -> Option<&'a str> {
+        use std::ffi::OsStr;
+
+        let url = url.trim_end_matches('/');
+        let name = url.rsplit(&['/', ':'][..]).next()?;
+
+        let as_path = Path::new(name);
+        let name = if as_path.extension() == Some(OsStr::new("git")) {
+            as_path.file_stem()?.to_str()?
+        } else {
+            name
+        };
+
+        match name {
+            "" | "." | ".." | ".git" => None,
+            name => Some(name),
+        }
+    }
+
+    /// Guesses who owns a repository from its URL: the path segment before the
+    /// repository's own, so `https://github.com/team/my-backend.git` and
+    /// `git@github.com:team/my-backend` both give `team`. A URL with only one path
+    /// segment has no owner.
+    pub fn owner_from_url(url: &'a str) -> Option<&'a str> {
+        let path = match url.find("://") {
+            // Skip the host, which can't be an owner
+            Some(scheme) => {
+                let rest = &url[scheme + 3..];
+                &rest[rest.find('/')? + 1..]
+            }
+            None => match (url.find(':'), url.find('/')) {
+                (Some(colon), None) => &url[colon + 1..],
+                (Some(colon), Some(slash)) if colon < slash => &url[colon + 1..],
+                _ => url,
+            },
+        };
+
+        let segments: Vec<_> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match segments.len() {
+            0 | 1 => None,
+            len => match segments[len - 2] {
+                "." | ".." => None,
+                owner => Some(owner),
+            },
+        }
+    }
+
     pub fn to_path_buf(self, scaii_dir: &Path) -> PathBuf {
+        self.layout_path(scaii_dir, OutputLayout::Nested, "")
+    }
+
+    /// Like `to_path_buf`, but with names placed according to `layout`. `url` is only
+    /// looked at for `OutputLayout::ByOwner`, which falls back to `Nested` if the URL
+    /// has no owner.
+    pub fn layout_path(self, scaii_dir: &Path, layout: OutputLayout, url: &str) -> PathBuf {
         match self {
             NameOrPath::SavePath(path) => path.to_path_buf(),
             NameOrPath::Name(name) => {
                 let mut scaii_dir = scaii_dir.to_path_buf();
-                scaii_dir.push("git");
+                if layout != OutputLayout::Flat {
+                    scaii_dir.push("git");
+                }
+                if layout == OutputLayout::ByOwner {
+                    if let Some(owner) = NameOrPath::owner_from_url(url) {
+                        scaii_dir.push(owner);
+                    }
+                }
                 scaii_dir.push(name);
                 scaii_dir
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{NameOrPath, OutputLayout};
+    use std::path::Path;
+
+    #[test]
+    fn name_from_url_strips_git_suffix() {
+        assert_eq!(
+            NameOrPath::name_from_url("https://github.com/team/my-backend.git"),
+            Some("my-backend")
+        );
+        assert_eq!(
+            NameOrPath::name_from_url("git@github.com:team/my-backend.git"),
+            Some("my-backend")
+        );
+        assert_eq!(
+            NameOrPath::name_from_url("https://github.com/team/my-backend"),
+            Some("my-backend")
+        );
+    }
+
+    #[test]
+    fn name_from_url_trailing_slash() {
+        assert_eq!(
+            NameOrPath::name_from_url("https://github.com/team/my-backend/"),
+            Some("my-backend")
+        );
+        assert_eq!(
+            NameOrPath::name_from_url("https://github.com/team/my-backend.git//"),
+            Some("my-backend")
+        );
+    }
+
+    #[test]
+    fn owner_from_url() {
+        assert_eq!(
+            NameOrPath::owner_from_url("https://github.com/team/my-backend.git"),
+            Some("team")
+        );
+        assert_eq!(NameOrPath::owner_from_url("git@github.com:team/my-backend"), Some("team"));
+        assert_eq!(NameOrPath::owner_from_url("ssh://git@host:2222/a/b/c/"), Some("b"));
+        assert_eq!(NameOrPath::owner_from_url("/srv/git/my-backend"), Some("git"));
+
+        assert_eq!(NameOrPath::owner_from_url("https://github.com/my-backend"), None);
+        assert_eq!(NameOrPath::owner_from_url("host:my-backend"), None);
+        assert_eq!(NameOrPath::owner_from_url("../my-backend"), None);
+    }
+
+    #[test]
+    fn layouts() {
+        let home = Path::new("/home/me/.scaii");
+        let url = "https://github.com/team/my-backend.git";
+        let path = |layout, url| NameOrPath::Name("my-backend").layout_path(home, layout, url);
+
+        assert_eq!(path(OutputLayout::Nested, url), home.join("git/my-backend"));
+        assert_eq!(path(OutputLayout::Flat, url), home.join("my-backend"));
+        assert_eq!(path(OutputLayout::ByOwner, url), home.join("git/team/my-backend"));
+        assert_eq!(
+            path(OutputLayout::ByOwner, "https://example.com/my-backend"),
+            home.join("git/my-backend")
+        );
+
+        // Each layout's depth limit is how far below the home its installs are at least
+        for &layout in &[OutputLayout::Nested, OutputLayout::Flat] {
+            let depth = path(layout, url).strip_prefix(home).unwrap().components().count();
+            assert_eq!(layout.depth_limit(), depth);
+        }
+        assert_eq!(OutputLayout::ByOwner.depth_limit(), 2);
+
+        let save_path = NameOrPath::SavePath(Path::new("/elsewhere"));
+        assert_eq!(
+            save_path.layout_path(home, OutputLayout::Flat, url),
+            Path::new("/elsewhere")
+        );
+    }
+
+    #[test]
+    fn name_from_url_nothing_to_infer() {
+        assert_eq!(NameOrPath::name_from_url("https://"), None);
+        assert_eq!(NameOrPath::name_from_url("https://github.com/team/.git"), None);
+        assert_eq!(NameOrPath::name_from_url("../"), None);
+        assert_eq!(NameOrPath::name_from_url(""), None);
+    }
+}
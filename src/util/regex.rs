@@ -0,0 +1,393 @@
+//! Just enough of regular expressions for `--find-commit`: literals, `.`, `[...]` classes
+//! with ranges and `^` negation, the `\d`, `\w` and `\s` classes (and their negations),
+//! groups, `|`, the `*`, `+` and `?` repetitions and the `^` and `$` anchors. Other escaped
+//! characters stand for themselves.
+//!
+//! A pattern is compiled to a small backtracking program. A state that failed to match
+//! fails from wherever it's reached, so each one is only tried once and searching takes
+//! time linear in the length of the text.
+
+use error;
+
+/// A compiled pattern, searched for anywhere in the text it's matched against.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    /// Tries the first branch, then the second
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(low, high)| low <= c && c <= high) != self.negated
+    }
+}
+
+/// A parsed pattern, before it's compiled.
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    /// The node, with how many times it has to, and may at most, occur
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> error::Result<Regex> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let node = parser
+            .alternation()
+            .and_then(|node| match parser.peek() {
+                Some(')') => Err("unmatched ')'".to_string()),
+                _ => Ok(node),
+            })
+            .map_err(|err| format!("Invalid pattern '{}': {}", pattern, err))?;
+
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+
+        Ok(Regex {
+            source: pattern.to_string(),
+            program,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut failed = vec![false; self.program.len() * (text.len() + 1)];
+
+        (0..text.len() + 1).any(|start| self.run(&text, start, &mut failed))
+    }
+
+    fn run(&self, text: &[char], start: usize, failed: &mut [bool]) -> bool {
+        let mut stack = vec![(0, start)];
+
+        while let Some((pc, pos)) = stack.pop() {
+            let state = pc * (text.len() + 1) + pos;
+            if failed[state] {
+                continue;
+            }
+            failed[state] = true;
+
+            match (&self.program[pc], text.get(pos)) {
+                (Inst::Char(c), Some(next)) if c == next => stack.push((pc + 1, pos + 1)),
+                (Inst::Any, Some(_)) => stack.push((pc + 1, pos + 1)),
+                (Inst::Class(class), Some(&next)) if class.matches(next) => {
+                    stack.push((pc + 1, pos + 1))
+                }
+                (Inst::Start, _) if pos == 0 => stack.push((pc + 1, pos)),
+                (Inst::End, None) => stack.push((pc + 1, pos)),
+                (&Inst::Split(first, second), _) => {
+                    stack.push((second, pos));
+                    stack.push((first, pos));
+                }
+                (&Inst::Jump(to), _) => stack.push((to, pos)),
+                (Inst::Match, _) => return true,
+                _ => {}
+            }
+        }
+
+        false
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match *node {
+        Node::Char(c) => program.push(Inst::Char(c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(ref class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(ref nodes) => for node in nodes {
+            compile(node, program);
+        },
+        Node::Alternate(ref nodes) => {
+            // Each branch but the last splits off to the next one, and jumps past the rest
+            let mut jumps = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 == nodes.len() {
+                    compile(node, program);
+                    break;
+                }
+
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(node, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                let next = program.len();
+                program[split] = Inst::Split(split + 1, next);
+            }
+
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(ref node, min, max) => {
+            for _ in 0..min {
+                compile(node, program);
+            }
+
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => for _ in min..max {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                },
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek();
+        self.pos += 1;
+        next
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concatenation()?);
+        }
+
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+
+            let atom = self.atom()?;
+            nodes.push(self.repetition(atom)?);
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn repetition(&mut self, mut node: Node) -> Result<Node, String> {
+        while let Some(c) = self.peek() {
+            let (min, max) = match c {
+                '*' => (0, None),
+                '+' => (1, None),
+                '?' => (0, Some(1)),
+                _ => break,
+            };
+            self.pos += 1;
+
+            match node {
+                Node::Start | Node::End => return Err(format!("nothing to repeat before '{}'", c)),
+                _ => node = Node::Repeat(Box::new(node), min, max),
+            }
+        }
+
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some('(') => {
+                // A non-capturing group is just a group, since nothing is captured anyway
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let node = self.alternation()?;
+                match self.next() {
+                    Some(')') => Ok(node),
+                    _ => Err("unclosed '('".to_string()),
+                }
+            }
+            Some('[') => self.class().map(Node::Class),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => match self.next() {
+                Some(c) => Ok(match shorthand(c) {
+                    Some(class) => Node::Class(class),
+                    None => Node::Char(c),
+                }),
+                None => Err("trailing '\\'".to_string()),
+            },
+            Some(c) if c == '*' || c == '+' || c == '?' => {
+                Err(format!("nothing to repeat before '{}'", c))
+            }
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    /// Parses a class, after its `[`.
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let low = match self.next() {
+                // A `]` right at the start is just a `]`
+                Some(']') if !first => break,
+                Some('\\') => match self.next() {
+                    Some(c) => match shorthand(c) {
+                        Some(Class { negated: false, ranges: shorthand }) => {
+                            ranges.extend(shorthand);
+                            first = false;
+                            continue;
+                        }
+                        Some(_) => return Err(format!("'\\{}' can't be used in a class", c)),
+                        None => c,
+                    },
+                    None => return Err("unclosed '['".to_string()),
+                },
+                Some(c) => c,
+                None => return Err("unclosed '['".to_string()),
+            };
+            first = false;
+
+            // A `-` right at the end is just a `-`
+            let is_range = match (self.peek(), self.chars.get(self.pos + 1)) {
+                (Some('-'), Some(&c)) => c != ']',
+                _ => false,
+            };
+            if is_range {
+                self.pos += 1;
+                let high = match self.next() {
+                    Some('\\') => self.next().ok_or_else(|| "unclosed '['".to_string())?,
+                    Some(c) => c,
+                    None => return Err("unclosed '['".to_string()),
+                };
+                if high < low {
+                    return Err(format!("range '{}-{}' is backwards", low, high));
+                }
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+
+        Ok(Class { ranges, negated })
+    }
+}
+
+/// The class a `\d`, `\w` or `\s` (or their upper case negations) stands for.
+fn shorthand(c: char) -> Option<Class> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+
+    Some(Class {
+        ranges,
+        negated: c.is_ascii_uppercase(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Regex;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn searches_unanchored() {
+        assert!(matches("Release v1", "Bump to Release v1.2"));
+        assert!(matches("v1\\.\\d+", "Release v1.12"));
+        assert!(!matches("v1\\.\\d+", "Release v1x12"));
+        assert!(matches("^Release", "Release v1"));
+        assert!(!matches("^Release", "Pre-Release v1"));
+        assert!(matches("v\\d$", "Release v1"));
+        assert!(!matches("v\\d$", "Release v1.0"));
+        assert!(matches("", "anything"));
+    }
+
+    #[test]
+    fn classes_groups_and_repetition() {
+        assert!(matches("^[Rr]elease(d|s)? [^ ]+$", "released v2"));
+        assert!(!matches("^[Rr]elease(d|s)? [^ ]+$", "released v2 now"));
+        assert!(matches("^(ab)*c$", "ababc"));
+        assert!(!matches("^(ab)*c$", "abac"));
+        assert!(matches("^a+b?$", "aaa"));
+        assert!(matches("^[]a-]+$", "]-a"));
+        assert!(matches("^[\\d.]+$", "1.2.3"));
+        assert!(matches("^\\W\\S\\s.$", "!a x"));
+        assert!(matches("^(?:fix|feat)\\(.*\\):", "feat(get): add a flag"));
+
+        // Nested repetition doesn't backtrack exponentially
+        let long = format!("{}b", "a".repeat(5_000));
+        assert!(!matches("^(a*)*c", &long));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in &["(a", "a)", "[a", "a\\", "*a", "a|+", "[z-a]", "^*", "[\\D]"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use error;
+
+/// How the delay between retries grows.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Backoff {
+    /// Always wait `base`
+    Constant,
+    /// Wait `base`, then `2 * base`, `3 * base`...
+    Linear,
+    /// Wait `base`, then `2 * base`, `4 * base`...
+    Exponential,
+}
+
+/// How many times, and how patiently, a failing network operation is attempted.
+///
+/// Parsed from the compact form given to `--retry-policy`, a comma separated list of
+/// `key=value` pairs where any key may be left out to keep its default, e.g.
+/// `attempts=3,backoff=exponential,base=500ms,max=10s`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first, so `1` never retries
+    pub attempts: u32,
+    pub backoff: Backoff,
+    /// The delay before the first retry
+    pub base: Duration,
+    /// No delay is ever longer than this
+    pub max: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Backoff::Exponential,
+            base: Duration::from_millis(500),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that waits the same `delay` before each of its `retries` retries.
+    pub fn constant(retries: u32, delay: Duration) -> Self {
+        RetryPolicy {
+            attempts: retries + 1,
+            backoff: Backoff::Constant,
+            base: delay,
+            max: delay,
+        }
+    }
+
+    pub fn parse(policy: &str) -> error::Result<Self> {
+        let mut parsed = RetryPolicy::default();
+
+        for setting in policy.split(',') {
+            let mut parts = setting.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => bail!(
+                    "Invalid retry policy '{}': expected 'key=value', got '{}'",
+                    policy,
+                    setting
+                ),
+            };
+
+            match key {
+                "attempts" => {
+                    parsed.attempts = match value.parse() {
+                        Ok(attempts) if attempts > 0 => attempts,
+                        _ => bail!(
+                            "Invalid retry policy '{}': attempts must be a positive number, got '{}'",
+                            policy,
+                            value
+                        ),
+                    }
+                }
+                "backoff" => {
+                    parsed.backoff = match value {
+                        "constant" => Backoff::Constant,
+                        "linear" => Backoff::Linear,
+                        "exponential" => Backoff::Exponential,
+                        _ => bail!(
+                            "Invalid retry policy '{}': backoff must be one of 'constant', \
+                             'linear' or 'exponential', got '{}'",
+                            policy,
+                            value
+                        ),
+                    }
+                }
+                "base" => parsed.base = parse_duration(policy, value)?,
+                "max" => parsed.max = parse_duration(policy, value)?,
+                _ => bail!(
+                    "Invalid retry policy '{}': unknown key '{}' (Note: keys are 'attempts', \
+                     'backoff', 'base' and 'max')",
+                    policy,
+                    key
+                ),
+            }
+        }
+
+        ensure!(
+            parsed.base <= parsed.max,
+            "Invalid retry policy '{}': base delay is longer than the max delay",
+            policy
+        );
+
+        Ok(parsed)
+    }
+
+    /// The number of retries after the first attempt.
+    pub fn retries(&self) -> u32 {
+        self.attempts.saturating_sub(1)
+    }
+
+    /// How long to wait before retry number `retry`, counting from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = match self.backoff {
+            Backoff::Constant => 1,
+            Backoff::Linear => retry.saturating_add(1),
+            Backoff::Exponential => 1 << retry.min(31),
+        };
+
+        self.base
+            .checked_mul(factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// The HTTP statuses a download is retried on rather than failed with at once.
+///
+/// Parsed from `--retry-on-http`, a comma separated list of exact statuses and classes
+/// such as `5xx`, e.g. `5xx,429`. `none` never retries on a status.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RetryStatuses {
+    /// Inclusive ranges, a class is the range of its hundred
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Default for RetryStatuses {
+    fn default() -> Self {
+        use constants::DEFAULT_RETRY_ON_HTTP;
+
+        RetryStatuses::parse(DEFAULT_RETRY_ON_HTTP).unwrap()
+    }
+}
+
+impl RetryStatuses {
+    pub fn parse(statuses: &str) -> error::Result<Self> {
+        if statuses == "none" {
+            return Ok(RetryStatuses { ranges: Vec::new() });
+        }
+
+        let mut ranges = Vec::new();
+        for status in statuses.split(',').map(str::trim) {
+            let (digits, class) = if status.len() == 3 && status.ends_with("xx") {
+                (&status[..1], true)
+            } else {
+                (status, false)
+            };
+            let range = match digits.parse::<u32>() {
+                Ok(hundred @ 1..=5) if class => {
+                    (hundred * 100, hundred * 100 + 99)
+                }
+                Ok(code @ 100..=599) if !class => (code, code),
+                _ => bail!(
+                    "Invalid HTTP statuses '{}': expected a status like '503' or a class like \
+                     '5xx', got '{}' (Note: 'none' turns retrying on a status off)",
+                    statuses,
+                    status
+                ),
+            };
+            ranges.push(range);
+        }
+
+        Ok(RetryStatuses { ranges })
+    }
+
+    pub fn contains(&self, status: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(first, last)| first <= status && status <= last)
+    }
+}
+
+/// Parses a duration such as `250ms`, `3s` or `1m`. A unit is required.
+fn parse_duration(policy: &str, duration: &str) -> error::Result<Duration> {
+    let (number, unit) = duration.split_at(
+        duration
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(duration.len()),
+    );
+
+    let number: u64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => bail!(
+            "Invalid retry policy '{}': expected a duration like '500ms' or '10s', got '{}'",
+            policy,
+            duration
+        ),
+    };
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number.saturating_mul(60))),
+        _ => bail!(
+            "Invalid retry policy '{}': duration '{}' needs a unit of 'ms', 's' or 'm'",
+            policy,
+            duration
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backoff, RetryPolicy, RetryStatuses};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_full_policy() {
+        assert_eq!(
+            RetryPolicy::parse("attempts=4,backoff=linear,base=250ms,max=1s").unwrap(),
+            RetryPolicy {
+                attempts: 4,
+                backoff: Backoff::Linear,
+                base: Duration::from_millis(250),
+                max: Duration::from_secs(1),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_keeps_defaults() {
+        assert_eq!(
+            RetryPolicy::parse("attempts=1").unwrap(),
+            RetryPolicy {
+                attempts: 1,
+                ..RetryPolicy::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert!(RetryPolicy::parse("").is_err());
+        assert!(RetryPolicy::parse("attempts").is_err());
+        assert!(RetryPolicy::parse("attempts=0").is_err());
+        assert!(RetryPolicy::parse("attempts=-1").is_err());
+        assert!(RetryPolicy::parse("backoff=random").is_err());
+        assert!(RetryPolicy::parse("base=500").is_err());
+        assert!(RetryPolicy::parse("base=fast").is_err());
+        assert!(RetryPolicy::parse("tries=3").is_err());
+        assert!(RetryPolicy::parse("base=20s,max=10s").is_err());
+    }
+
+    #[test]
+    fn exponential_delays_are_capped() {
+        let policy = RetryPolicy::parse("backoff=exponential,base=1s,max=5s").unwrap();
+        let delays: Vec<_> = (0..5).map(|retry| policy.delay(retry).as_secs()).collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        assert_eq!(policy.delay(1_000), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn retry_statuses() {
+        let default = RetryStatuses::default();
+        assert!(default.contains(500));
+        assert!(default.contains(503));
+        assert!(default.contains(429));
+        assert!(!default.contains(404));
+        assert!(!default.contains(200));
+
+        let exact = RetryStatuses::parse("502, 4xx").unwrap();
+        assert!(exact.contains(502) && exact.contains(404));
+        assert!(!exact.contains(503));
+        assert!(!RetryStatuses::parse("none").unwrap().contains(503));
+
+        assert!(RetryStatuses::parse("").is_err());
+        assert!(RetryStatuses::parse("6xx").is_err());
+        assert!(RetryStatuses::parse("5x").is_err());
+        assert!(RetryStatuses::parse("99").is_err());
+        assert!(RetryStatuses::parse("5xx,none").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! SHA-1, for checking SSH host keys and bundle contents. It's long broken for signatures,
+//! but it's what `known_hosts` lookups need and it's plenty to catch a damaged file.
+
+use std::io::{self, Read};
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// The SHA-1 of everything `input` has left, read a block at a time.
+pub fn sha1_reader<R: Read>(mut input: R) -> io::Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    let mut buf = [0; 8192];
+    loop {
+        match input.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            read => hasher.update(&buf[..read]),
+        }
+    }
+}
+
+/// A SHA-1 fed piece by piece, for data that shouldn't be held in memory all at once.
+#[derive(Clone, Debug)]
+pub struct Sha1 {
+    h: [u32; 5],
+    /// What's left over from the last `update` that doesn't make a whole chunk yet
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Sha1 {
+            h: [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0],
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        self.pending.extend_from_slice(data);
+
+        let whole = self.pending.len() - self.pending.len() % 64;
+        for chunk in self.pending[..whole].chunks(64) {
+            compress(&mut self.h, chunk);
+        }
+        self.pending.drain(..whole);
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        while (self.pending.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        for i in (0..8).rev() {
+            padding.push((bit_len >> (i * 8)) as u8);
+        }
+        self.pending.extend_from_slice(&padding);
+        for chunk in self.pending.chunks(64) {
+            compress(&mut self.h, chunk);
+        }
+
+        let mut digest = [0u8; 20];
+        for (i, word) in self.h.iter().enumerate() {
+            for j in 0..4 {
+                digest[i * 4 + j] = (word >> (24 - j * 8)) as u8;
+            }
+        }
+
+        digest
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Sha1::new()
+    }
+}
+
+/// Mixes one 64 byte chunk into the state `h`.
+fn compress(h: &mut [u32; 5], chunk: &[u8]) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+        w[i] = (u32::from(chunk[i * 4]) << 24) | (u32::from(chunk[i * 4 + 1]) << 16)
+            | (u32::from(chunk[i * 4 + 2]) << 8) | u32::from(chunk[i * 4 + 3]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+    for (i, &word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+
+        let temp = a.rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    h[0] = h[0].wrapping_add(a);
+    h[1] = h[1].wrapping_add(b);
+    h[2] = h[2].wrapping_add(c);
+    h[3] = h[3].wrapping_add(d);
+    h[4] = h[4].wrapping_add(e);
+}
+
+/// Lowercase hex, the way digests are usually written out.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sha1, sha1_reader, to_hex, Sha1};
+
+    #[test]
+    fn sha1_digests() {
+        assert_eq!(to_hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(to_hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(to_hex(&sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn sha1_in_pieces() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut hasher = Sha1::new();
+        for piece in data.chunks(37) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), sha1(&data));
+        assert_eq!(sha1_reader(&data[..]).unwrap(), sha1(&data));
+    }
+}
@@ -0,0 +1,181 @@
+//! Joining the volumes of a split zip archive (`.z01`, `.z02`, ..., `.zip`, as written by
+//! `zip -s`) back into a single archive, which is what `zip -s 0` does.
+//!
+//! A split archive is the single archive cut into pieces, except that it starts with a
+//! spanning marker and every offset in its central directory is counted from the start of
+//! the volume it points into. Joining concatenates the volumes, drops the marker, and
+//! rewrites those offsets to count from the start of the whole. Zip64 split archives
+//! aren't supported.
+
+use error;
+
+/// The marker a split archive's first volume starts with.
+const SPANNING_MARKER: &[u8] = b"PK\x07\x08";
+/// The marker an archive meant to be split but that fit in one volume starts with.
+const SINGLE_SPAN_MARKER: &[u8] = b"PK00";
+
+const CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+
+/// Whether `volume` is a piece of a split archive rather than a whole archive: either
+/// the first volume, or one whose end of central directory says it isn't the only disk.
+pub fn is_split_volume(volume: &[u8]) -> bool {
+    if volume.starts_with(SPANNING_MARKER) {
+        return true;
+    }
+
+    match find_end_of_central_directory(volume) {
+        Some(end) => u16_at(volume, end + 4) > 0 || u16_at(volume, end + 6) > 0,
+        None => false,
+    }
+}
+
+/// Joins the volumes of a split archive, given in order with the `.zip` last, into one
+/// archive `ZipArchive` can read.
+pub fn join_volumes(volumes: &[Vec<u8>]) -> error::Result<Vec<u8>> {
+    let mut joined = Vec::with_capacity(volumes.iter().map(Vec::len).sum());
+    let mut starts = Vec::with_capacity(volumes.len());
+    for volume in volumes {
+        starts.push(joined.len());
+        joined.extend_from_slice(volume);
+    }
+
+    let marker = if joined.starts_with(SPANNING_MARKER) || joined.starts_with(SINGLE_SPAN_MARKER) {
+        SPANNING_MARKER.len()
+    } else {
+        0
+    };
+
+    let last_start = *starts.last().unwrap_or(&0);
+    let end = match find_end_of_central_directory(&joined[last_start..]) {
+        Some(end) => last_start + end,
+        None => bail!("The last volume of the split archive has no end of central directory"),
+    };
+
+    let disks = usize::from(u16_at(&joined, end + 4)) + 1;
+    ensure!(
+        disks == volumes.len(),
+        "The split archive has {} volumes, but {} were found (Hint: every volume has to be \
+         in the same directory, numbered without gaps)",
+        disks,
+        volumes.len()
+    );
+
+    let (directory_disk, entries) = (u16_at(&joined, end + 6), u16_at(&joined, end + 10));
+    let directory_offset = u32_at(&joined, end + 16);
+    ensure!(
+        entries != 0xffff && directory_offset != 0xffff_ffff,
+        "The split archive is a zip64 archive (Note: only plain split zips can be joined)"
+    );
+
+    // Offsets in the joined archive, once the marker is dropped
+    let len = joined.len();
+    let absolute = |disk: u16, offset: u32| -> error::Result<u32> {
+        let start = match starts.get(usize::from(disk)) {
+            Some(&start) => start,
+            None => bail!("An entry of the split archive is on volume {}, past the last", disk),
+        };
+
+        match (start + offset as usize).checked_sub(marker) {
+            Some(absolute) if absolute < len => Ok(absolute as u32),
+            _ => bail!("The central directory of the split archive is damaged"),
+        }
+    };
+
+    let directory = absolute(directory_disk, directory_offset)? as usize + marker;
+    let mut pos = directory;
+    for _ in 0..entries {
+        ensure!(
+            pos + 46 <= joined.len() && joined[pos..].starts_with(CENTRAL_HEADER),
+            "The central directory of the split archive is damaged"
+        );
+
+        let offset = absolute(u16_at(&joined, pos + 34), u32_at(&joined, pos + 42))?;
+        set_u16(&mut joined, pos + 34, 0);
+        set_u32(&mut joined, pos + 42, offset);
+
+        pos += 46 + usize::from(u16_at(&joined, pos + 28)) + usize::from(u16_at(&joined, pos + 30))
+            + usize::from(u16_at(&joined, pos + 32));
+    }
+
+    set_u16(&mut joined, end + 4, 0);
+    set_u16(&mut joined, end + 6, 0);
+    set_u16(&mut joined, end + 8, entries);
+    set_u32(&mut joined, end + 16, (directory - marker) as u32);
+
+    Ok(joined.split_off(marker))
+}
+
+/// Where the end of central directory record starts, looking back from the end past any
+/// archive comment.
+fn find_end_of_central_directory(buf: &[u8]) -> Option<usize> {
+    let last = buf.len().checked_sub(END_OF_CENTRAL_DIRECTORY_LEN)?;
+    // The record ends with a comment of up to 0xffff bytes
+    let first = last.saturating_sub(0xffff);
+
+    (first..=last)
+        .rev()
+        .find(|&pos| buf[pos..].starts_with(END_OF_CENTRAL_DIRECTORY))
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from(buf[pos]) | u16::from(buf[pos + 1]) << 8
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from(u16_at(buf, pos)) | u32::from(u16_at(buf, pos + 2)) << 16
+}
+
+fn set_u16(buf: &mut [u8], pos: usize, value: u16) {
+    buf[pos] = value as u8;
+    buf[pos + 1] = (value >> 8) as u8;
+}
+
+fn set_u32(buf: &mut [u8], pos: usize, value: u32) {
+    set_u16(buf, pos, value as u16);
+    set_u16(buf, pos + 2, (value >> 16) as u16);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_split_volume, join_volumes};
+    use util::fixture;
+
+    #[test]
+    fn joins_two_volumes() {
+        use std::io::{Cursor, Read};
+        use zip::ZipArchive;
+
+        let archive = fixture::zip_fixture(&[
+            ("top/", b""),
+            ("top/a.txt", &[b'a'; 300]),
+            ("top/b.txt", b"b"),
+        ]);
+        // The spanning marker makes the volumes 4 bytes longer than the archive
+        let volumes = fixture::split_zip_fixture(&archive, (archive.len() + 4) / 2 + 1);
+        assert_eq!(volumes.len(), 2);
+        assert!(volumes.iter().all(|volume| is_split_volume(volume)));
+        assert!(!is_split_volume(&archive));
+
+        let joined = join_volumes(&volumes).unwrap();
+        let mut joined = ZipArchive::new(Cursor::new(joined)).unwrap();
+        let mut contents = Vec::new();
+        joined.by_name("top/a.txt").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![b'a'; 300]);
+        contents.clear();
+        joined.by_name("top/b.txt").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"b");
+    }
+
+    #[test]
+    fn missing_volumes() {
+        let archive = fixture::zip_fixture(&[("a.txt", &[b'a'; 300]), ("b.txt", b"b")]);
+        let volumes = fixture::split_zip_fixture(&archive, 64);
+        assert!(volumes.len() > 2);
+
+        assert!(join_volumes(&volumes[1..]).is_err());
+        assert!(join_volumes(&volumes[..1]).is_err());
+        assert!(join_volumes(&[]).is_err());
+    }
+}
@@ -0,0 +1,619 @@
+//! Just enough of the tar format for bundles and GitHub tarballs: ustar headers, plus GNU
+//! long name entries and pax `path` and `linkpath` records for paths that don't fit them.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use error;
+use util;
+
+const BLOCK: usize = 512;
+
+/// The most a long name or pax header may hold, which is far more than any real one does.
+/// Those are read into memory, unlike files, so a damaged size can't run it out.
+const MAX_HEADER_DATA: u64 = 1 << 20;
+
+/// Writes a tar archive entry by entry. Paths always use `/`, and directories should
+/// come before what's in them.
+pub struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> Self {
+        TarWriter { out }
+    }
+
+    pub fn append_dir(&mut self, path: &str, mode: u32) -> io::Result<()> {
+        let path = format!("{}/", path.trim_end_matches('/'));
+        self.append(&path, mode, b'5', "", &[])
+    }
+
+    pub fn append_file(&mut self, path: &str, mode: u32, contents: &[u8]) -> io::Result<()> {
+        self.append(path, mode, b'0', "", contents)
+    }
+
+    pub fn append_symlink(&mut self, path: &str, target: &str) -> io::Result<()> {
+        self.append(path, 0o777, b'2', target, &[])
+    }
+
+    /// Writes a file the way `git archive` does when its path doesn't fit ustar: a pax
+    /// header with the path, then the file under a made up name.
+    #[cfg(test)]
+    pub fn append_pax_file(&mut self, path: &str, mode: u32, contents: &[u8]) -> io::Result<()> {
+        let body = format!(" path={}\n", path);
+        let mut len = body.len() + 1;
+        while len.to_string().len() + body.len() != len {
+            len += 1;
+        }
+
+        let record = format!("{}{}", len, body);
+        self.append("pax_header", 0o666, b'x', "", record.as_bytes())?;
+        self.append("9cd84cd917211467eefefd69b6f20ee21e305059.data", mode, b'0', "", contents)
+    }
+
+    /// Writes the end of archive marker, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0; BLOCK * 2])?;
+        Ok(self.out)
+    }
+
+    fn append(
+        &mut self,
+        path: &str,
+        mode: u32,
+        kind: u8,
+        link: &str,
+        contents: &[u8],
+    ) -> io::Result<()> {
+        if link.len() > 100 {
+            self.append_long_name(b'K', link)?;
+        }
+        let name = match split_path(path) {
+            Some(name) => name,
+            None => {
+                self.append_long_name(b'L', path)?;
+                ("", truncate(path, 100))
+            }
+        };
+
+        self.out.write_all(&header(name, mode, contents.len() as u64, kind, truncate(link, 100)))?;
+        self.write_padded(contents)
+    }
+
+    /// A GNU `././@LongLink` entry, which holds the full name or link target of the entry
+    /// right after it.
+    fn append_long_name(&mut self, kind: u8, name: &str) -> io::Result<()> {
+        let mut contents = name.as_bytes().to_vec();
+        contents.push(0);
+
+        let head = header(("", "././@LongLink"), 0, contents.len() as u64, kind, "");
+        self.out.write_all(&head)?;
+        self.write_padded(&contents)
+    }
+
+    fn write_padded(&mut self, contents: &[u8]) -> io::Result<()> {
+        self.out.write_all(contents)?;
+
+        let padding = (BLOCK - contents.len() % BLOCK) % BLOCK;
+        self.out.write_all(&[0; BLOCK][..padding])
+    }
+}
+
+/// The kinds of entry `TarReader` tells apart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Devices, FIFOs, hard links and the like, which bundles never contain
+    Other,
+}
+
+/// A single entry read by `TarReader`, with any long name or pax path already applied.
+/// Its contents are read from the `TarReader` itself.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Entry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    /// The target, for symlinks
+    pub link: String,
+    /// How long the contents are, as the header says
+    pub size: u64,
+}
+
+/// Reads a tar archive entry by entry. Reading from it reads the contents of the entry
+/// `next_entry` returned last, and whatever of them isn't read is skipped by the next call.
+pub struct TarReader<R: Read> {
+    input: R,
+    comment: Option<String>,
+    /// What's left of the contents of the current entry
+    remaining: u64,
+    /// The padding after them, up to the next header
+    padding: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(input: R) -> Self {
+        TarReader {
+            input,
+            comment: None,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// The `comment` of the global pax header read so far, which is where GitHub puts the
+    /// commit a tarball was made from.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_ref().map(|comment| &comment[..])
+    }
+
+    /// The next entry, or `None` at the end of the archive. Of the pax headers, only the
+    /// `path` and `linkpath` of the entry after them and a global `comment` are applied.
+    pub fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        let (mut long_name, mut long_link) = (None, None);
+
+        let unread = self.remaining + self.padding;
+        self.skip(unread)?;
+        self.remaining = 0;
+        self.padding = 0;
+
+        loop {
+            let mut head = [0u8; BLOCK];
+            self.input.read_exact(&mut head)?;
+            if head.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+
+            let stored = octal(&head[148..156])?;
+            let sum = head[..148]
+                .iter()
+                .chain(&head[156..])
+                .map(|&b| u64::from(b))
+                .sum::<u64>() + 8 * u64::from(b' ');
+            if stored != sum {
+                return Err(invalid("tar header checksum mismatch"));
+            }
+
+            let size = octal(&head[124..136])?;
+            let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+
+            let kind = match head[156] {
+                b'L' => {
+                    long_name = Some(text(&self.header_data(size, padding)?)?);
+                    continue;
+                }
+                b'K' => {
+                    long_link = Some(text(&self.header_data(size, padding)?)?);
+                    continue;
+                }
+                b'g' => {
+                    let contents = self.header_data(size, padding)?;
+                    if let Some(comment) = pax_record(&contents, "comment") {
+                        self.comment = Some(comment);
+                    }
+                    continue;
+                }
+                // `git archive` names the entry after a hash when the path is in here
+                b'x' => {
+                    let contents = self.header_data(size, padding)?;
+                    if let Some(path) = pax_record(&contents, "path") {
+                        long_name = Some(path);
+                    }
+                    if let Some(link) = pax_record(&contents, "linkpath") {
+                        long_link = Some(link);
+                    }
+                    continue;
+                }
+                b'0' | b'\0' => EntryKind::File,
+                b'5' => EntryKind::Dir,
+                b'2' => EntryKind::Symlink,
+                _ => EntryKind::Other,
+            };
+
+            let path = match long_name {
+                Some(path) => path,
+                None => match (text(&head[345..500])?, text(&head[..100])?) {
+                    (ref prefix, name) if prefix.is_empty() => name,
+                    (prefix, name) => format!("{}/{}", prefix, name),
+                },
+            };
+
+            let entry = Entry {
+                path,
+                kind,
+                mode: octal(&head[100..108])? as u32,
+                link: match long_link {
+                    Some(link) => link,
+                    None => text(&head[157..257])?,
+                },
+                size,
+            };
+            self.remaining = size;
+            self.padding = padding;
+
+            return Ok(Some(entry));
+        }
+    }
+
+    /// The contents of a long name or pax header entry, and the padding after them.
+    fn header_data(&mut self, size: u64, padding: u64) -> io::Result<Vec<u8>> {
+        if size > MAX_HEADER_DATA {
+            return Err(invalid("tar extended header is too large"));
+        }
+
+        let mut contents = Vec::with_capacity(size as usize);
+        (&mut self.input).take(size).read_to_end(&mut contents)?;
+        if contents.len() as u64 != size {
+            return Err(truncated());
+        }
+        self.skip(padding)?;
+
+        Ok(contents)
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        if io::copy(&mut (&mut self.input).take(len), &mut io::sink())? != len {
+            return Err(truncated());
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = (buf.len() as u64).min(self.remaining) as usize;
+        let read = self.input.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(truncated());
+        }
+        self.remaining -= read as u64;
+
+        Ok(read)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "tar archive is truncated")
+}
+
+/// The value of `key` in pax header `contents`, which is made of `<len> <key>=<value>\n`
+/// records with `<len>` counting the whole record.
+fn pax_record(contents: &[u8], key: &str) -> Option<String> {
+    let mut rest = contents;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let len: usize = ::std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        if len <= space + 1 || len > rest.len() {
+            return None;
+        }
+
+        let record = ::std::str::from_utf8(&rest[space + 1..len]).ok()?;
+        let mut parts = record.trim_end_matches('\n').splitn(2, '=');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name == key {
+                return Some(value.to_string());
+            }
+        }
+        rest = &rest[len..];
+    }
+
+    None
+}
+
+/// A NUL padded header field, or the contents of a long name entry.
+fn text(field: &[u8]) -> io::Result<String> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+
+    String::from_utf8(field[..end].to_vec()).map_err(|_| invalid("tar entry name is not UTF-8"))
+}
+
+/// A NUL or space terminated octal header field.
+fn octal(field: &[u8]) -> io::Result<u64> {
+    let digits = text(field)?;
+    let digits = digits.trim_matches(' ');
+
+    u64::from_str_radix(digits, 8).map_err(|_| invalid("malformed number in tar header"))
+}
+
+/// Splits `path` into the ustar prefix and name fields, if it fits them.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    // The prefix and name are joined with a `/`, so only a `/` can be split at
+    path.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|&(_, name)| !name.is_empty())
+}
+
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+fn header((prefix, name): (&str, &str), mode: u32, size: u64, kind: u8, link: &str) -> [u8; BLOCK] {
+    let mut head = [0u8; BLOCK];
+
+    let mut field = |offset: usize, value: &[u8]| {
+        head[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name.as_bytes());
+    field(100, format!("{:07o}\0", mode & 0o7777).as_bytes());
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    // A fixed time keeps bundles of the same tree identical
+    field(136, b"00000000000\0");
+    field(156, &[kind]);
+    field(157, link.as_bytes());
+    field(257, b"ustar\0");
+    field(263, b"00");
+    field(345, prefix.as_bytes());
+
+    // The checksum is calculated with its own field taken to be spaces
+    let sum = head.iter().map(|&b| u32::from(b)).sum::<u32>() + 8 * u32::from(b' ');
+    head[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+
+    head
+}
+
+/// Writes entries under `target`, refusing any that would end up outside of it: absolute
+/// paths, `..`, symlinks to absolute paths and anything under a symlink an earlier entry
+/// made, which could point anywhere.
+pub struct Extractor<'a> {
+    target: &'a Path,
+    dir_mode: Option<u32>,
+    links: Vec<PathBuf>,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn new(target: &'a Path, dir_mode: Option<u32>) -> Self {
+        Extractor {
+            target,
+            dir_mode,
+            links: Vec::new(),
+        }
+    }
+
+    /// Writes `entry` to `relative` under the target, streaming a file's contents from
+    /// `contents`. `within` names the target in the error when `relative` is outside of it.
+    pub fn extract<R: Read>(
+        &mut self,
+        entry: &Entry,
+        contents: &mut R,
+        relative: &Path,
+        within: &str,
+    ) -> error::Result<()> {
+        use std::path::Component;
+
+        ensure!(
+            !relative.as_os_str().is_empty() && relative.is_relative()
+                && !relative.components().any(|part| part == Component::ParentDir)
+                && !self.links.iter().any(|link| relative.starts_with(link)),
+            "Refusing to extract '{}', which is outside of {}",
+            entry.path,
+            within
+        );
+        let out = self.target.join(relative);
+
+        match entry.kind {
+            EntryKind::Dir => util::create_dir_all(&out, self.dir_mode)?,
+            EntryKind::File => {
+                io::copy(contents, &mut fs::File::create(&out)?)?;
+                set_mode(&out, entry.mode)?;
+            }
+            EntryKind::Symlink => {
+                ensure!(
+                    !Path::new(&entry.link).has_root(),
+                    "Refusing to extract '{}', a symlink to the absolute path '{}'",
+                    entry.path,
+                    entry.link
+                );
+                symlink(&entry.link, &out)?;
+                self.links.push(relative.to_path_buf());
+            }
+            EntryKind::Other => bail!("'{}' is not a file, directory or symlink", entry.path),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &str, link: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(target, link)
+}
+
+/// Symlinks need extra privileges on Windows, so they become plain files holding their
+/// target, just as git checks them out there by default.
+#[cfg(not(unix))]
+pub fn symlink(target: &str, link: &Path) -> io::Result<()> {
+    fs::write(link, target)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use super::{header, split_path, EntryKind, TarReader, TarWriter};
+
+    /// The path, kind and contents of each entry.
+    fn entries(tar: &[u8]) -> Vec<(String, EntryKind, Vec<u8>)> {
+        let mut reader = TarReader::new(tar);
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().unwrap() {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).unwrap();
+            entries.push((entry.path, entry.kind, contents));
+        }
+
+        entries
+    }
+
+    #[test]
+    fn writes_entries() {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let longer = "x".repeat(300);
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_dir("core", 0o755).unwrap();
+        tar.append_file("core/a.txt", 0o644, b"hello").unwrap();
+        tar.append_symlink("core/link", "a.txt").unwrap();
+        tar.append_file(&long, 0o644, &[1; 600]).unwrap();
+        tar.append_file(&longer, 0o644, b"").unwrap();
+        let tar = tar.finish().unwrap();
+
+        assert_eq!(tar.len() % 512, 0);
+        assert_eq!(
+            entries(&tar),
+            vec![
+                ("core/".to_string(), EntryKind::Dir, vec![]),
+                ("core/a.txt".to_string(), EntryKind::File, b"hello".to_vec()),
+                ("core/link".to_string(), EntryKind::Symlink, vec![]),
+                (long, EntryKind::File, vec![1; 600]),
+                (longer, EntryKind::File, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_links_and_modes() {
+        let long_target = "t".repeat(150);
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_file("run.sh", 0o755, b"#!/bin/sh").unwrap();
+        tar.append_symlink("link", &long_target).unwrap();
+        let tar = tar.finish().unwrap();
+
+        let mut reader = TarReader::new(&tar[..]);
+        let script = reader.next_entry().unwrap().unwrap();
+        assert_eq!(script.mode, 0o755);
+        assert_eq!(reader.next_entry().unwrap().unwrap().link, long_target);
+        assert_eq!(reader.next_entry().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_damaged_headers() {
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_file("a.txt", 0o644, b"hello").unwrap();
+        let mut tar = tar.finish().unwrap();
+        tar[0] = b'b';
+
+        assert!(TarReader::new(&tar[..]).next_entry().is_err());
+        assert!(TarReader::new(&tar[..100]).next_entry().is_err());
+    }
+
+    #[test]
+    fn skips_unread_contents() {
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_file("a.txt", 0o644, &[1; 700]).unwrap();
+        tar.append_file("b.txt", 0o644, b"b").unwrap();
+        let tar = tar.finish().unwrap();
+
+        let mut reader = TarReader::new(&tar[..]);
+        assert_eq!(reader.next_entry().unwrap().unwrap().size, 700);
+        let mut start = [0; 10];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, "b.txt");
+        assert_eq!(reader.next_entry().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_entries() {
+        let huge = (1 << 33) - 1;
+
+        // A file is only read as far as it goes, so claiming more than is there fails
+        let mut tar = header(("", "a.txt"), 0o644, huge, b'0', "").to_vec();
+        tar.extend_from_slice(&[0; 1024]);
+        let mut reader = TarReader::new(&tar[..]);
+        assert_eq!(reader.next_entry().unwrap().unwrap().size, huge);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+        let long_name = header(("", "././@LongLink"), 0, huge, b'L', "");
+        assert!(TarReader::new(&long_name[..]).next_entry().is_err());
+    }
+
+    #[test]
+    fn reads_global_comment() {
+        let commit = "9cd84cd917211467eefefd69b6f20ee21e305059";
+        let record = format!("52 comment={}\n", commit);
+        assert_eq!(record.len(), 52);
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append("pax_global_header", 0o666, b'g', "", record.as_bytes()).unwrap();
+        tar.append_dir("repo-master", 0o755).unwrap();
+        let tar = tar.finish().unwrap();
+
+        let mut reader = TarReader::new(&tar[..]);
+        assert_eq!(reader.comment(), None);
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, "repo-master/");
+        assert_eq!(reader.comment(), Some(commit));
+
+        assert_eq!(super::pax_record(b"12 path=a/b\n", "comment"), None);
+        assert_eq!(super::pax_record(b"99 comment=x\n", "comment"), None);
+    }
+
+    #[test]
+    fn reads_pax_paths() {
+        let long = format!("repo-master/{}/main.rs", "src".repeat(40));
+        let link = format!("{}.rs", "t".repeat(120));
+        let record = format!("{} linkpath={}\n", 137, link);
+        assert_eq!(record.len(), 137);
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_pax_file(&long, 0o644, b"fn main() {}").unwrap();
+        tar.append("pax_header", 0o666, b'x', "", record.as_bytes()).unwrap();
+        tar.append_symlink("repo-master/link", "t").unwrap();
+        tar.append_file("repo-master/a.txt", 0o644, b"a").unwrap();
+        let tar = tar.finish().unwrap();
+
+        let mut reader = TarReader::new(&tar[..]);
+        let file = reader.next_entry().unwrap().unwrap();
+        assert_eq!((&file.path[..], file.kind), (&long[..], EntryKind::File));
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "fn main() {}");
+        let symlink = reader.next_entry().unwrap().unwrap();
+        assert_eq!((&symlink.path[..], &symlink.link[..]), ("repo-master/link", &link[..]));
+        // The records only apply to the entry right after them
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, "repo-master/a.txt");
+    }
+
+    #[test]
+    fn splits_long_paths() {
+        let path = format!("{}/{}", "a".repeat(150), "b".repeat(100));
+        assert_eq!(split_path(&path), Some((&path[..150], &path[151..])));
+        assert_eq!(split_path(&"a".repeat(101)), None);
+        assert_eq!(split_path(&format!("{}/", "a".repeat(101))), None);
+    }
+}
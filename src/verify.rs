@@ -0,0 +1,214 @@
+use clap::ArgMatches;
+use std::path::{Path, PathBuf};
+
+use error::{self, CLEAN_EXIT, MISSING_CHECKOUT_EXIT, MISSING_DEPS_EXIT, WRONG_COMMIT_EXIT};
+
+use util::{self, NameOrPath};
+use constants::*;
+
+/// Something `Verify::verify` found wrong with an installed core.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Problem {
+    /// There's no git checkout at the expected path
+    MissingCheckout,
+    /// The checkout's `HEAD` isn't the expected commit
+    WrongCommit,
+    /// A dependency directory is missing or empty
+    MissingDeps,
+}
+
+impl Problem {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Problem::MissingCheckout => MISSING_CHECKOUT_EXIT,
+            Problem::WrongCommit => WRONG_COMMIT_EXIT,
+            Problem::MissingDeps => MISSING_DEPS_EXIT,
+        }
+    }
+}
+
+/// Checks, without repairing anything, that the core is installed the way `get core`
+/// would leave it.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Verify<'a> {
+    path: PathBuf,
+    commit: Option<&'a str>,
+    /// Where the dependencies should be, relative to the checkout
+    dep_dir: PathBuf,
+}
+
+impl<'a> Verify<'a> {
+    pub fn from_subcommand(
+        subcommand: &'a ArgMatches<'a>,
+        scaii_dir: &Path,
+    ) -> error::Result<Self> {
+        let resource = subcommand.subcommand();
+        let (resource, args) = (resource.0, resource.1.unwrap());
+
+        let commit = args.value_of("commit");
+        if let Some(commit) = commit {
+            ensure!(
+                !commit.is_empty() && commit.chars().all(|c| c.is_ascii_hexdigit()),
+                "Invalid commit '{}' (Note: expected a full or abbreviated commit hash)",
+                commit
+            );
+        }
+
+        let mut verify = match resource {
+            "core" => Verify::new_core(args.value_of("save-path"), commit, scaii_dir),
+            _ => usage_and_exit!(subcommand),
+        };
+        if let Some(dir) = args.value_of("dep-extract-dir") {
+            verify.dep_dir = util::parse_relative_dir("dependency extract directory", dir)?;
+        }
+
+        Ok(verify)
+    }
+
+    pub fn new_core(save_path: Option<&'a str>, commit: Option<&'a str>, scaii_dir: &Path) -> Self {
+        Verify {
+            path: NameOrPath::from_path_or_default(save_path, CORE_NAME).to_path_buf(scaii_dir),
+            commit,
+            dep_dir: PathBuf::from(DEP_EXTRACT_DIR),
+        }
+    }
+
+    /// Prints every problem found, returning them worst first. A missing checkout hides
+    /// any other problem.
+    pub fn verify(&self) -> Vec<Problem> {
+        use get::core_resource_dirs;
+
+        let head = match head_commit(&self.path) {
+            Some(head) => head,
+            None => {
+                log_println!("No git checkout of the core at '{}'", self.path.display());
+                return vec![Problem::MissingCheckout];
+            }
+        };
+
+        let mut problems = Vec::new();
+
+        if let Some(commit) = self.commit {
+            if !head.starts_with(&*commit.to_lowercase()) {
+                log_println!("The core is at {}, expected {}", head, commit);
+                problems.push(Problem::WrongCommit);
+            }
+        }
+
+        let missing: Vec<_> = core_resource_dirs(&self.path, &self.dep_dir)
+            .iter()
+            .filter(|dir| !util::has_entries(dir))
+            .map(|dir| format!("'{}'", dir.display()))
+            .collect();
+        if !missing.is_empty() {
+            log_println!("Missing or empty dependencies: {}", missing.join(", "));
+            problems.push(Problem::MissingDeps);
+        }
+
+        if problems.is_empty() {
+            log_println!("The core at '{}' is healthy ({})", self.path.display(), head);
+        }
+
+        problems
+    }
+
+    /// The exit status for a set of `problems`, that of the worst one.
+    pub fn exit_code(problems: &[Problem]) -> i32 {
+        problems.first().map_or(CLEAN_EXIT, |problem| problem.exit_code())
+    }
+}
+
+/// The commit checked out at `path`, if it is a git checkout.
+#[cfg(windows)]
+pub fn head_commit(path: &Path) -> Option<String> {
+    use std::process::Command;
+
+    if !path.is_dir() {
+        return None;
+    }
+
+    let head = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+
+    if head.status.success() {
+        Some(String::from_utf8_lossy(&head.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// The commit checked out at `path`, if it is a git checkout.
+#[cfg(not(windows))]
+pub fn head_commit(path: &Path) -> Option<String> {
+    use git2::Repository;
+
+    let repo = Repository::open(path).ok()?;
+    let head = repo.head().ok()?.target()?;
+
+    Some(head.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Problem, Verify};
+    use error::CLEAN_EXIT;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn verify_core_layout() {
+        use std::fs;
+        use git2::{Repository, Signature};
+        use fs2;
+        use get::core_resource_dirs;
+        use constants::DEP_EXTRACT_DIR;
+        use std::path::Path;
+        use util::fixture::temp_dir;
+
+        let root = temp_dir("verify-core");
+        let core = root.join("SCAII");
+        let verify = |commit| Verify::new_core(core.to_str(), commit, &root).verify();
+
+        assert_eq!(verify(None), vec![Problem::MissingCheckout]);
+
+        let head = {
+            let repo = Repository::init(&core).unwrap();
+            let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+            let signature = Signature::now("test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+                .unwrap()
+                .to_string()
+        };
+
+        // Present but empty still counts as missing
+        let deps = core_resource_dirs(&core, Path::new(DEP_EXTRACT_DIR));
+        fs::create_dir_all(&deps[0]).unwrap();
+        fs::create_dir_all(&deps[1]).unwrap();
+        fs::write(deps[0].join("base.js"), "").unwrap();
+        assert_eq!(verify(None), vec![Problem::MissingDeps]);
+        assert_eq!(
+            verify(Some("0000000")),
+            vec![Problem::WrongCommit, Problem::MissingDeps]
+        );
+
+        fs::write(deps[1].join("message.js"), "").unwrap();
+        assert_eq!(verify(None), vec![]);
+        assert_eq!(verify(Some(&head[..7])), vec![]);
+        assert_eq!(verify(Some(&*head.to_uppercase())), vec![]);
+
+        fs2::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn exit_code_of_worst_problem() {
+        assert_eq!(Verify::exit_code(&[]), CLEAN_EXIT);
+        assert_eq!(
+            Verify::exit_code(&[Problem::WrongCommit, Problem::MissingDeps]),
+            Problem::WrongCommit.exit_code()
+        );
+    }
+}